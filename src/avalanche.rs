@@ -0,0 +1,289 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::sandpile::*;
+use crate::zhang::Zhang;
+
+/// A pile that grains can be dropped on, one at a time
+pub trait Driven {
+    /// `[hgt, wth]`
+    fn dims(&self) -> [usize; 2];
+    /// Drop a grain on `[i, j]` and relax the pile
+    fn drop_grain(&mut self, i: usize, j: usize) -> Avalanche;
+}
+
+impl Driven for Sandpile {
+    fn dims(&self) -> [usize; 2] {
+        Sandpile::dims(self)
+    }
+
+    fn drop_grain(&mut self, i: usize, j: usize) -> Avalanche {
+        Sandpile::drop_grain(self, i, j)
+    }
+}
+
+impl Driven for Zhang {
+    fn dims(&self) -> [usize; 2] {
+        Zhang::dims(self)
+    }
+
+    fn drop_grain(&mut self, i: usize, j: usize) -> Avalanche {
+        Zhang::drop_grain(self, i, j)
+    }
+}
+
+/// Quantity of an avalanche to build a histogram of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Measure {
+    Size,
+    Area,
+    Waves,
+    Radius,
+}
+
+impl Measure {
+    fn of(self, a: &Avalanche) -> f64 {
+        match self {
+            Measure::Size => a.size as f64,
+            Measure::Area => a.area as f64,
+            Measure::Waves => a.waves as f64,
+            Measure::Radius => a.radius,
+        }
+    }
+}
+
+/// Record of the avalanches caused by dropping grains at random on a
+/// sandpile, to study self-organized criticality: once the pile has
+/// reached its critical state, the sizes of avalanches follow a power law.
+pub struct Avalanches<P: Driven = Sandpile> {
+    pile: P,
+    hgt: usize,
+    wth: usize,
+    records: Vec<Avalanche>,
+    rng: ChaCha8Rng,
+}
+
+impl Avalanches {
+    /// Start from an empty `hgt x wth` pile, grains fall at random
+    /// positions
+    pub fn new(hgt: usize, wth: usize) -> Self {
+        Self::on(Sandpile::new(hgt, wth), rand::thread_rng().gen())
+    }
+}
+
+impl<P: Driven> Avalanches<P> {
+    /// Drop grains on any pile (e.g. a `Sandpile` with another `Model`, or
+    /// a `Zhang` pile), at positions given by `seed` so that runs can be
+    /// reproduced
+    pub fn on(pile: P, seed: u64) -> Self {
+        let [hgt, wth] = pile.dims();
+        Self {
+            pile,
+            hgt,
+            wth,
+            records: Vec::new(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Drop `n` grains without recording anything, to let the pile reach
+    /// its critical state (about `2.1 * hgt * wth` grains are needed)
+    pub fn warm_up(&mut self, n: usize) {
+        for _ in 0..n {
+            let (i, j) = (
+                self.rng.gen_range(0, self.hgt),
+                self.rng.gen_range(0, self.wth),
+            );
+            self.pile.drop_grain(i, j);
+        }
+    }
+
+    /// Drop `n` grains at random and record the avalanches (including the
+    /// empty ones, where nothing topples)
+    pub fn run(&mut self, n: usize) {
+        for k in 0..n {
+            let (i, j) = (
+                self.rng.gen_range(0, self.hgt),
+                self.rng.gen_range(0, self.wth),
+            );
+            let aval = self.pile.drop_grain(i, j);
+            self.records.push(aval);
+            if (k + 1) % 1000 == 0 {
+                eprint!("\rDone grain {}/{}", k + 1, n);
+            }
+        }
+        eprintln!();
+    }
+
+    pub fn records(&self) -> &[Avalanche] {
+        &self.records
+    }
+
+    pub fn pile(&self) -> &P {
+        &self.pile
+    }
+
+    /// One line per avalanche
+    pub fn save_csv(&self, file: &str) {
+        let mut f = BufWriter::new(File::create(file).unwrap());
+        writeln!(f, "i,j,size,area,waves,radius").unwrap();
+        for a in &self.records {
+            writeln!(
+                f,
+                "{},{},{},{},{},{:.3}",
+                a.pos[0], a.pos[1], a.size, a.area, a.waves, a.radius
+            )
+            .unwrap();
+        }
+        f.flush().unwrap();
+    }
+
+    /// Histogram of the non-empty avalanches with logarithmic bins:
+    /// bin `k` holds the values in `[base^k, base^(k+1))`.
+    ///
+    /// Returns `(low, high, count, density)` for each bin, where the density
+    /// is the count divided by the width of the bin and the number of
+    /// values, so that a power law shows as a straight line on a log-log
+    /// plot.
+    pub fn histogram(&self, measure: Measure, base: f64) -> Vec<(f64, f64, usize, f64)> {
+        assert!(base > 1., "bins must grow");
+        let values: Vec<f64> = self
+            .records
+            .iter()
+            .map(|a| measure.of(a))
+            .filter(|&v| v >= 1.)
+            .collect();
+        let mut counts = Vec::new();
+        for v in &values {
+            let mut k = (v.ln() / base.ln()).floor() as usize;
+            // the logarithms are rounded, so exact powers of the base may
+            // land on either side of a bound: check against the bounds
+            // themselves
+            while k > 0 && base.powi(k as i32) > *v {
+                k -= 1;
+            }
+            while base.powi(k as i32 + 1) <= *v {
+                k += 1;
+            }
+            if counts.len() <= k {
+                counts.resize(k + 1, 0);
+            }
+            counts[k] += 1;
+        }
+        counts
+            .iter()
+            .enumerate()
+            .map(|(k, &n)| {
+                let (lo, hi) = (base.powi(k as i32), base.powi(k as i32 + 1));
+                (lo, hi, n, n as f64 / (hi - lo) / values.len() as f64)
+            })
+            .collect()
+    }
+
+    /// Write the histogram in columns `low high count density`
+    pub fn save_histogram(&self, file: &str, measure: Measure, base: f64) {
+        let mut f = BufWriter::new(File::create(file).unwrap());
+        writeln!(
+            f,
+            "# {:?} of {} avalanches on a {}x{} pile",
+            measure,
+            self.records.len(),
+            self.hgt,
+            self.wth
+        )
+        .unwrap();
+        for (lo, hi, n, d) in self.histogram(measure, base) {
+            writeln!(f, "{} {} {} {:e}", lo, hi, n, d).unwrap();
+        }
+        f.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aval(size: usize) -> Avalanche {
+        Avalanche {
+            pos: [0, 0],
+            size,
+            area: size.min(4),
+            waves: 1,
+            radius: 0.,
+        }
+    }
+
+    #[test]
+    fn single_drops() {
+        let mut pile = Sandpile::from_config(&Configuration::max_stable(1, 1));
+        let a = pile.drop_grain(0, 0);
+        assert_eq!((a.size, a.area, a.waves, a.radius), (1, 1, 1, 0.));
+        let a = pile.drop_grain(0, 0);
+        assert_eq!((a.size, a.area, a.waves), (0, 0, 0));
+        // the avalanche leaves the pile as `stabilize` would
+        let full = Configuration::max_stable(5, 5);
+        let mut pile = Sandpile::from_config(&full);
+        let a = pile.drop_grain(2, 2);
+        let mut grain = Configuration::filled(5, 5, 0);
+        grain.set(2, 2, 1);
+        assert_eq!(pile.config(), &full + &grain);
+        assert_eq!(a.area, 25);
+        assert!(a.size > a.area && a.waves > 1);
+        assert!((a.radius - 8f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn histogram() {
+        let mut run = Avalanches::on(Sandpile::new(2, 2), 0);
+        run.records = [0, 1, 2, 3, 4, 9].iter().map(|&s| aval(s)).collect();
+        let h = run.histogram(Measure::Size, 2.);
+        let counts: Vec<_> = h.iter().map(|&(lo, hi, n, _)| (lo, hi, n)).collect();
+        assert_eq!(
+            counts,
+            vec![(1., 2., 1), (2., 4., 2), (4., 8., 1), (8., 16., 1)]
+        );
+        // densities integrate to 1 over the non-empty avalanches
+        let total: f64 = h.iter().map(|&(lo, hi, _, d)| d * (hi - lo)).sum();
+        assert!((total - 1.).abs() < 1e-9);
+        assert_eq!(run.histogram(Measure::Area, 10.)[0].2, 5);
+    }
+
+    #[test]
+    fn histogram_exact_powers() {
+        let mut run = Avalanches::on(Sandpile::new(2, 2), 0);
+        run.records = [1, 9, 10, 99, 100, 999, 1000, 1001]
+            .iter()
+            .map(|&s| aval(s))
+            .collect();
+        let counts: Vec<_> = run
+            .histogram(Measure::Size, 10.)
+            .iter()
+            .map(|&(_, _, n, _)| n)
+            .collect();
+        assert_eq!(counts, vec![2, 2, 2, 2]);
+        run.records = [242, 243, 729].iter().map(|&s| aval(s)).collect();
+        let counts: Vec<_> = run
+            .histogram(Measure::Size, 3.)
+            .iter()
+            .map(|&(_, _, n, _)| n)
+            .collect();
+        assert_eq!(counts, vec![0, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn reproducible() {
+        let sizes = |seed| {
+            let mut run = Avalanches::on(Sandpile::new(8, 8), seed);
+            run.warm_up(100);
+            run.run(200);
+            run.records().iter().map(|a| a.size).collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(3), sizes(3));
+        assert_eq!(sizes(3).len(), 200);
+        let mut zhang = Avalanches::on(Zhang::new(8, 8, 1), 1);
+        zhang.run(500);
+        assert!(zhang.records().iter().any(|a| a.size > 0));
+    }
+}
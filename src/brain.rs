@@ -1,6 +1,7 @@
 use rand::Rng;
 
 use crate::canvas::*;
+use crate::history::*;
 
 /// A cell in Brian's brain can have any of three states
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -45,6 +46,7 @@ pub struct Brain {
     hgt: usize,
     wth: usize,
     fired: usize,
+    history: History<State>,
 }
 
 impl Brain {
@@ -55,6 +57,7 @@ impl Brain {
             hgt,
             wth,
             fired: 0,
+            history: History::new(0),
         }
     }
 
@@ -94,11 +97,22 @@ impl Brain {
 
     /// Actualize current state with previously calculated next state
     pub fn update(&mut self) {
+        let mut delta = Vec::new();
         for i in 0..self.hgt {
             for j in 0..self.wth {
+                let old = self.field[[i, j]].curr;
                 self.field[[i, j]].update();
+                let new = self.field[[i, j]].curr;
+                if old != new && self.history.is_enabled() {
+                    delta.push(Change {
+                        pos: [i, j],
+                        old,
+                        new,
+                    });
+                }
             }
         }
+        self.history.record(delta);
     }
 
     /// 2D Array access with automatic looping around the edges.
@@ -212,6 +226,23 @@ impl Brain {
     }
 }
 
+impl Rewind<State> for Brain {
+    fn history_mut(&mut self) -> &mut History<State> {
+        &mut self.history
+    }
+
+    fn travel(&mut self, forward: bool) -> bool {
+        self.fired = 0;
+        let (field, fired) = (&mut self.field, &mut self.fired);
+        self.history.travel(forward, |pos, s| {
+            field[pos].set(s);
+            if s == State::Firing {
+                *fired += 1;
+            }
+        })
+    }
+}
+
 impl Neuron {
     /// All neurons are initialized ready to fire
     pub fn new() -> Self {
@@ -234,4 +265,42 @@ impl Neuron {
     pub fn is_firing(self) -> bool {
         self.curr == State::Firing
     }
+
+    /// Overwrite both current and next state (used to travel through history)
+    pub fn set(&mut self, s: State) {
+        self.curr = s;
+        self.succ = s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(brain: &Brain) -> Vec<State> {
+        (0..brain.hgt * brain.wth)
+            .map(|k| brain.field[[k / brain.wth, k % brain.wth]].curr)
+            .collect()
+    }
+
+    #[test]
+    fn back_and_forward() {
+        let mut brain = Brain::new(20, 20);
+        brain.keep_history(5);
+        brain.init_rand(0.3);
+        let mut seen = vec![states(&brain)];
+        for _ in 0..5 {
+            brain.next();
+            seen.push(states(&brain));
+        }
+        assert_eq!(brain.rewind(10), 5);
+        assert!(states(&brain) == seen[0]);
+        for state in &seen[1..] {
+            assert!(brain.forward());
+            assert!(states(&brain) == *state);
+        }
+        brain.back();
+        brain.next();
+        assert!(states(&brain) == seen[5]);
+    }
 }
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::lifelike::*;
+use crate::period::*;
+
+/// Longest period looked for when classifying an object
+const MAX_PERIOD: usize = 64;
+
+/// Names of the most common objects, indexed by apgcode
+const NAMES: &[(&str, &str)] = &[
+    ("xs4_33", "block"),
+    ("xs4_252", "tub"),
+    ("xs5_253", "boat"),
+    ("xs6_356", "ship"),
+    ("xs6_696", "beehive"),
+    ("xs7_2596", "loaf"),
+    ("xs8_6996", "pond"),
+    ("xp2_7", "blinker"),
+    ("xp2_7e", "toad"),
+    ("xp2_318c", "beacon"),
+    ("xq4_153", "glider"),
+];
+
+/// Split a set of live cells into objects (groups of cells connected
+/// through the Moore neighborhood).
+///
+/// Connectivity does not wrap around the edges, soups are expected to
+/// stay away from them.
+pub fn objects(cells: &[[usize; 2]]) -> Vec<Vec<[isize; 2]>> {
+    let mut label: HashMap<[usize; 2], usize> = HashMap::new();
+    let mut res = Vec::new();
+    for &start in cells {
+        if label.contains_key(&start) {
+            continue;
+        }
+        let id = res.len();
+        let mut obj = Vec::new();
+        let mut stack = vec![start];
+        label.insert(start, id);
+        while let Some([i, j]) = stack.pop() {
+            obj.push([i as isize, j as isize]);
+            for di in 0..3 {
+                for dj in 0..3 {
+                    if i + di == 0 || j + dj == 0 {
+                        continue;
+                    }
+                    let n = [i + di - 1, j + dj - 1];
+                    if !label.contains_key(&n) && cells.binary_search(&n).is_ok() {
+                        label.insert(n, id);
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        res.push(obj);
+    }
+    res
+}
+
+/// Extended Wechsler format: the pattern is cut in strips of 5 lines,
+/// each column of a strip is a 5-bit digit, strips are separated by `z`
+/// and runs of blank columns are compressed with `w`, `x` and `y`.
+fn wechsler(cells: &[[isize; 2]]) -> String {
+    let imin = cells.iter().map(|c| c[0]).min().unwrap_or(0);
+    let jmin = cells.iter().map(|c| c[1]).min().unwrap_or(0);
+    let hgt = (cells.iter().map(|c| c[0]).max().unwrap_or(0) - imin + 1) as usize;
+    let wth = (cells.iter().map(|c| c[1]).max().unwrap_or(0) - jmin + 1) as usize;
+    let strips = hgt.div_ceil(5);
+    let mut cols = vec![vec![0u32; wth]; strips];
+    for [i, j] in cells {
+        let (i, j) = ((i - imin) as usize, (j - jmin) as usize);
+        cols[i / 5][j] |= 1 << (i % 5);
+    }
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut res = String::new();
+    for (k, strip) in cols.iter().enumerate() {
+        if k > 0 {
+            res.push('z');
+        }
+        let len = strip.iter().rposition(|&c| c != 0).map_or(0, |p| p + 1);
+        let mut zeros = 0;
+        let flush = |res: &mut String, zeros: &mut usize| {
+            while *zeros > 0 {
+                match *zeros {
+                    1 => res.push('0'),
+                    2 => res.push('w'),
+                    3 => res.push('x'),
+                    n => {
+                        let n = n.min(39);
+                        res.push('y');
+                        res.push(DIGITS[n - 4] as char);
+                        *zeros -= n;
+                        continue;
+                    }
+                }
+                *zeros = 0;
+            }
+        };
+        for &c in &strip[..len] {
+            if c == 0 {
+                zeros += 1;
+            } else {
+                flush(&mut res, &mut zeros);
+                res.push(DIGITS[c as usize] as char);
+            }
+        }
+    }
+    res
+}
+
+/// Canonical representation of a single phase: the shortest then
+/// lexicographically smallest encoding among all 8 transformations
+fn canonical(cells: &[[isize; 2]]) -> String {
+    T_ALL
+        .iter()
+        .map(|t| {
+            let img: Vec<_> = cells.iter().map(|&[i, j]| t.apply(i, j)).collect();
+            wechsler(&img)
+        })
+        .min_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)))
+        .unwrap()
+}
+
+/// Identify an object in the format used by apgsearch:
+/// `xs{population}_` for still lifes, `xp{period}_` for oscillators and
+/// `xq{period}_` for spaceships, followed by the canonical encoding of the
+/// object (minimized over all phases and transformations).
+///
+/// Objects that do not settle into a periodic behavior on their own
+/// (e.g. they were only stable thanks to a close neighbor) are reported as
+/// `zz_UNKNOWN`.
+pub fn apgcode(obj: &[[isize; 2]], rules: &str) -> String {
+    let imin = obj.iter().map(|c| c[0]).min().unwrap_or(0);
+    let jmin = obj.iter().map(|c| c[1]).min().unwrap_or(0);
+    let hgt = (obj.iter().map(|c| c[0]).max().unwrap_or(0) - imin + 1) as usize;
+    let wth = (obj.iter().map(|c| c[1]).max().unwrap_or(0) - jmin + 1) as usize;
+    let margin = MAX_PERIOD as isize;
+    let mut game = LifeLike::new(hgt + 2 * MAX_PERIOD, wth + 2 * MAX_PERIOD, rules);
+    game.add_cells(obj, margin - imin, margin - jmin);
+    let report = match classify(&mut game, MAX_PERIOD) {
+        Some(r) if r.stable_at == 0 => r,
+        _ => return String::from("zz_UNKNOWN"),
+    };
+    let (prefix, period) = match report.behavior {
+        Behavior::Dead => return String::from("zz_UNKNOWN"),
+        Behavior::StillLife => (format!("xs{}", obj.len()), 1),
+        Behavior::Oscillator(p) => (format!("xp{}", p), p),
+        Behavior::Spaceship { period, .. } => (format!("xq{}", period), period),
+    };
+    let mut best: Option<String> = None;
+    for _ in 0..period {
+        let cells: Vec<_> = game
+            .live_cells()
+            .iter()
+            .map(|&[i, j]| [i as isize, j as isize])
+            .collect();
+        let code = canonical(&cells);
+        best = match best {
+            Some(b) if (b.len(), &b) <= (code.len(), &code) => Some(b),
+            _ => Some(code),
+        };
+        game.next();
+    }
+    format!("{}_{}", prefix, best.unwrap())
+}
+
+/// Common name of an object, if it has one
+pub fn name(code: &str) -> Option<&'static str> {
+    NAMES.iter().find(|(c, _)| *c == code).map(|(_, n)| *n)
+}
+
+/// Tally of the objects found in the ashes of many random soups
+pub struct Census {
+    rules: String,
+    size: usize,
+    density: f64,
+    max_gen: usize,
+    soups: usize,
+    unstable: usize,
+    counts: HashMap<String, usize>,
+}
+
+impl Census {
+    /// Soups are squares of side `size` where each cell is alive with
+    /// probability `density`
+    pub fn new(rules: &str, size: usize, density: f64) -> Self {
+        Self {
+            rules: String::from(rules),
+            size,
+            density,
+            max_gen: 20000,
+            soups: 0,
+            unstable: 0,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Run a single soup to stabilization and add its objects to the tally.
+    ///
+    /// A soup is considered stable once its population has been periodic for
+    /// a while, which allows gliders to escape without waiting for them to
+    /// wrap around the field.
+    pub fn soup(&mut self) {
+        let side = self.size * 8;
+        let mut game = LifeLike::new(side, side, &self.rules);
+        game.init_cluster(1. / 8., self.density);
+        let mut pop = Vec::new();
+        let mut stable = false;
+        for _ in 0..self.max_gen {
+            pop.push(game.population());
+            if is_periodic(&pop) {
+                stable = true;
+                break;
+            }
+            game.next();
+        }
+        self.soups += 1;
+        if !stable {
+            self.unstable += 1;
+            return;
+        }
+        for obj in objects(&game.live_cells()) {
+            *self.counts.entry(apgcode(&obj, &self.rules)).or_insert(0) += 1;
+        }
+    }
+
+    /// Run `n` soups
+    pub fn search(&mut self, n: usize) {
+        for k in 0..n {
+            self.soup();
+            eprint!("\rDone soup {}/{}", k + 1, n);
+        }
+        eprintln!();
+    }
+
+    /// All objects found so far, most common first
+    pub fn tally(&self) -> Vec<(&str, usize)> {
+        let mut v: Vec<_> = self.counts.iter().map(|(c, n)| (c.as_str(), *n)).collect();
+        v.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        v
+    }
+
+    /// Write the tally to a file, one object per line
+    pub fn save(&self, file: &str) {
+        let mut f = BufWriter::new(File::create(file).unwrap());
+        writeln!(f, "# rule {}", self.rules).unwrap();
+        writeln!(
+            f,
+            "# {} soups of {}x{} at density {} ({} did not stabilize)",
+            self.soups, self.size, self.size, self.density, self.unstable
+        )
+        .unwrap();
+        for (code, n) in self.tally() {
+            writeln!(f, "{} {} {}", code, n, name(code).unwrap_or("")).unwrap();
+        }
+        f.flush().unwrap();
+    }
+}
+
+/// The last few population counts repeat with some period
+fn is_periodic(pop: &[usize]) -> bool {
+    let n = pop.len();
+    (1..=MAX_PERIOD)
+        .any(|p| n > 4 * p + 16 && (0..3 * p + 16).all(|k| pop[n - 1 - k] == pop[n - 1 - k - p]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    #[test]
+    fn known_objects() {
+        for &(code, _) in NAMES {
+            let pat = Pattern::from_apgcode(code).unwrap();
+            for t in &T_ALL {
+                let cells: Vec<_> = pat.transform(t).translate(5, 7).cells().copied().collect();
+                assert_eq!(apgcode(&cells, LIFE), code, "{:?}", t);
+            }
+        }
+        assert_eq!(name("xq4_153"), Some("glider"));
+        assert_eq!(name("xs4_252"), Some("tub"));
+        assert_eq!(name("xq4_27"), None);
+    }
+
+    #[test]
+    fn unknown_objects() {
+        // a lone cell dies, a domino dies
+        assert_eq!(apgcode(&[[0, 0]], LIFE), "zz_UNKNOWN");
+        assert_eq!(apgcode(&[[0, 0], [0, 1]], LIFE), "zz_UNKNOWN");
+        // the R-pentomino takes over a thousand generations to settle
+        let r = [[0, 1], [0, 2], [1, 0], [1, 1], [2, 1]];
+        assert_eq!(apgcode(&r, LIFE), "zz_UNKNOWN");
+    }
+
+    #[test]
+    fn split_objects() {
+        // a block next to a blinker, and a lone cell touching it diagonally
+        let cells = [
+            [0, 0],
+            [0, 1],
+            [1, 0],
+            [1, 1],
+            [2, 2],
+            [5, 0],
+            [5, 1],
+            [5, 2],
+        ];
+        let objs = objects(&cells);
+        assert_eq!(objs.len(), 2);
+        assert_eq!(objs[0].len(), 5);
+        assert_eq!(objs[1].len(), 3);
+        assert_eq!(apgcode(&objs[1], LIFE), "xp2_7");
+    }
+
+    #[test]
+    fn periodic_population() {
+        let mut pop = vec![7; 40];
+        assert!(is_periodic(&pop));
+        pop.push(8);
+        assert!(!is_periodic(&pop));
+        let blinking: Vec<_> = (0..40).map(|k| 3 + k % 2).collect();
+        assert!(is_periodic(&blinking));
+    }
+}
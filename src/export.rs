@@ -0,0 +1,280 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// A rectangular area of a game of life, line by line
+pub type Region = Vec<Vec<bool>>;
+
+/// Supported pattern file formats.
+/// See [LifeWiki](https://www.conwaylife.com/wiki/File_formats) for details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Raw text: `x` is alive, `.` is dead and ` ` is left unchanged
+    Txt,
+    /// Run Length Encoded: `x = {}, y = {}, rule = B{}/S{}` then `b`, `o`, `$`
+    Rle,
+    /// Plaintext: `.` and `O`, `!` for comments
+    Cells,
+    /// Life 1.05: `.` and `*` under a `#P` block header
+    Life105,
+    /// Life 1.06: one `x y` line per live cell
+    Life106,
+    /// Golly's macrocell: a quadtree of 8x8 leaves (import only)
+    Macrocell,
+}
+
+impl Format {
+    /// Guess the format from the file extension.
+    /// `*.lif` files are assumed to be Life 1.05, the parser double-checks
+    /// the header when reading.
+    pub fn from_file(file: &str) -> Option<Self> {
+        match file.split('.').next_back()? {
+            "txt" => Some(Format::Txt),
+            "rle" => Some(Format::Rle),
+            "cells" => Some(Format::Cells),
+            "lif" | "life" => Some(Format::Life105),
+            "mc" => Some(Format::Macrocell),
+            _ => None,
+        }
+    }
+}
+
+/// Longest line allowed in a RLE file
+const RLE_WIDTH: usize = 70;
+
+/// Append a single `{count}{tag}` item to the RLE body, wrapping lines
+fn rle_push(out: &mut String, line: &mut usize, cnt: usize, tag: char) {
+    let item = if cnt > 1 {
+        format!("{}{}", cnt, tag)
+    } else {
+        tag.to_string()
+    };
+    if *line + item.len() > RLE_WIDTH {
+        out.push('\n');
+        *line = 0;
+    }
+    *line += item.len();
+    out.push_str(&item);
+}
+
+/// Encode a region as RLE, `rule` is written in `B3/S23` notation
+pub fn to_rle(region: &[Vec<bool>], rule: &str) -> String {
+    let hgt = region.len();
+    let wth = region.first().map_or(0, |l| l.len());
+    let mut out = format!("x = {}, y = {}, rule = {}\n", wth, hgt, rule);
+    let mut line = 0;
+    // blank lines are accumulated and only written when followed by a cell
+    let mut newlines = 0;
+    for row in region {
+        let len = row.iter().rposition(|&c| c).map_or(0, |p| p + 1);
+        if len > 0 {
+            if newlines > 0 {
+                rle_push(&mut out, &mut line, newlines, '$');
+                newlines = 0;
+            }
+            let mut k = 0;
+            while k < len {
+                let state = row[k];
+                let run = row[k..len].iter().take_while(|&&c| c == state).count();
+                rle_push(&mut out, &mut line, run, if state { 'o' } else { 'b' });
+                k += run;
+            }
+        }
+        newlines += 1;
+    }
+    rle_push(&mut out, &mut line, 1, '!');
+    out.push('\n');
+    out
+}
+
+/// Encode a region as raw text
+pub fn to_txt(region: &[Vec<bool>]) -> String {
+    let mut out = String::new();
+    for row in region {
+        for &c in row {
+            out.push(if c { 'x' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Encode a region as plaintext
+pub fn to_cells(region: &[Vec<bool>], name: &str) -> String {
+    let mut out = format!("!Name: {}\n", name);
+    for row in region {
+        for &c in row {
+            out.push(if c { 'O' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Encode a region as a single Life 1.05 block, `rule` is written in `23/3`
+/// notation
+pub fn to_life105(region: &[Vec<bool>], rule: &str) -> String {
+    let mut out = format!("#Life 1.05\n#R {}\n#P 0 0\n", rule);
+    for row in region {
+        for &c in row {
+            out.push(if c { '*' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Encode a region as a list of coordinates (column first)
+pub fn to_life106(region: &[Vec<bool>]) -> String {
+    let mut out = String::from("#Life 1.06\n");
+    for (i, row) in region.iter().enumerate() {
+        for (j, &c) in row.iter().enumerate() {
+            if c {
+                out.push_str(&format!("{} {}\n", j, i));
+            }
+        }
+    }
+    out
+}
+
+/// Write an already encoded pattern to a file
+pub fn write(file: &str, contents: &str) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(file)?);
+    f.write_all(contents.as_bytes())?;
+    f.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifelike::*;
+    use crate::parse::*;
+    use crate::pattern::Pattern;
+
+    /// Glider above a blinker, with a blank line and a blank column
+    fn region() -> Region {
+        ["..#....", "...#...", ".###...", ".......", "....###"]
+            .iter()
+            .map(|l| l.chars().map(|c| c == '#').collect())
+            .collect()
+    }
+
+    fn cells(region: &[Vec<bool>]) -> Pattern {
+        Pattern::from_rows(
+            &region
+                .iter()
+                .map(|r| r.iter().map(|&c| Some(c)).collect())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let region = region();
+        let encoded = [
+            (Format::Txt, to_txt(&region)),
+            (Format::Rle, to_rle(&region, "B3/S23")),
+            (Format::Cells, to_cells(&region, "glider")),
+            (Format::Life105, to_life105(&region, "23/3")),
+            (Format::Life106, to_life106(&region)),
+        ];
+        for (fmt, text) in &encoded {
+            assert_eq!(sniff(text), Some(*fmt), "{}", text);
+            let parsed = parse_str(text, *fmt).unwrap();
+            assert_eq!(
+                Pattern::from_rows(&parsed.rows).normalize(),
+                cells(&region).normalize(),
+                "{:?}",
+                fmt
+            );
+            if let Format::Rle | Format::Life105 = fmt {
+                assert_eq!(parsed.rule.as_deref(), Some("3-23"));
+            }
+        }
+        assert_eq!(
+            to_rle(&region, "B3/S23"),
+            "x = 7, y = 5, rule = B3/S23\n2bo$3bo$b3o2$4b3o!\n"
+        );
+    }
+
+    #[test]
+    fn long_rle_lines() {
+        let region: Region = (0..3)
+            .map(|i| (0..200).map(|j| (i + j) % 3 == 0).collect())
+            .collect();
+        let text = to_rle(&region, "B3/S23");
+        assert!(text.lines().all(|l| l.len() <= RLE_WIDTH));
+        let parsed = parse_str(&text, Format::Rle).unwrap();
+        assert_eq!(Pattern::from_rows(&parsed.rows), cells(&region));
+    }
+
+    #[test]
+    fn save_and_reload() {
+        let dir = std::env::temp_dir();
+        for ext in &["rle", "cells", "lif", "txt"] {
+            let file = dir.join(format!("export_round_trip.{}", ext));
+            let file = file.to_str().unwrap();
+            let mut game = LifeLike::new(20, 20, LIFE);
+            game.add_rows(
+                &region()
+                    .iter()
+                    .map(|r| r.iter().map(|&c| Some(c)).collect())
+                    .collect::<Vec<_>>(),
+                6,
+                4,
+                T_NONE,
+            );
+            game.save_to_file(file).unwrap();
+            let copy = LifeLike::from_file(file, 1).unwrap();
+            assert_eq!(
+                Pattern::from_game(&copy).normalize(),
+                Pattern::from_game(&game).normalize(),
+                "{}",
+                ext
+            );
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn save_as() {
+        let dir = std::env::temp_dir();
+        let mut game = LifeLike::new(20, 20, LIFE);
+        game.add_cells(&[[0, 1], [1, 2], [2, 0], [2, 1], [2, 2]], 3, 5);
+        // `*.lif` defaults to Life 1.05, 1.06 has to be asked for
+        let lif = dir.join("export_save_as.lif");
+        let lif = lif.to_str().unwrap();
+        game.save_as(lif, Format::Life106).unwrap();
+        let text = std::fs::read_to_string(lif).unwrap();
+        assert_eq!(sniff(&text), Some(Format::Life106));
+        let copy = LifeLike::from_file(lif, 1).unwrap();
+        assert_eq!(
+            Pattern::from_game(&copy).normalize(),
+            Pattern::from_game(&game).normalize()
+        );
+        std::fs::remove_file(lif).unwrap();
+        // plaintext files are named after the file, not the whole path
+        let cells = dir.join("export_glider.cells");
+        game.save_to_file(cells.to_str().unwrap()).unwrap();
+        let text = std::fs::read_to_string(&cells).unwrap();
+        assert!(text.starts_with("!Name: export_glider\n"), "{}", text);
+        std::fs::remove_file(&cells).unwrap();
+        // errors are reported instead of panicking
+        let kind = |r: io::Result<()>| r.unwrap_err().kind();
+        let unknown = dir.join("export_save_as.png");
+        assert_eq!(
+            kind(game.save_to_file(unknown.to_str().unwrap())),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(!unknown.exists());
+        let mc = dir.join("export_save_as.mc");
+        assert_eq!(
+            kind(game.save_to_file(mc.to_str().unwrap())),
+            io::ErrorKind::InvalidInput
+        );
+        let missing = dir.join("no_such_dir").join("glider.rle");
+        assert_eq!(
+            kind(game.save_to_file(missing.to_str().unwrap())),
+            io::ErrorKind::NotFound
+        );
+    }
+}
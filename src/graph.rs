@@ -0,0 +1,343 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::canvas::*;
+use crate::parse::ParseError;
+use crate::sandpile::shade;
+
+/// A directed multigraph with a sink: the vertex where grains disappear.
+///
+/// Lattices built by `grid`, `hex` and `triangular` keep their layout:
+/// vertex `i * wth + j` is drawn at `[i, j]`, and the sink comes last.
+/// Vertices on the border have edges to the sink for each missing
+/// neighbor, so that all vertices have the same degree.
+pub struct Graph {
+    adj: Vec<Vec<usize>>,
+    sink: usize,
+    /// `[hgt, wth]` of the layout, if any
+    layout: Option<[usize; 2]>,
+}
+
+impl Graph {
+    /// Empty graph with `n` vertices (plus the sink)
+    fn with_vertices(n: usize) -> Self {
+        Self {
+            adj: vec![Vec::new(); n + 1],
+            sink: n,
+            layout: None,
+        }
+    }
+
+    /// Lattice where the neighbors of `[i, j]` are the `[i + di, j + dj]`
+    /// returned by `moves(i, j)`
+    fn lattice<F>(hgt: usize, wth: usize, moves: F) -> Self
+    where
+        F: Fn(usize, usize) -> Vec<[isize; 2]>,
+    {
+        let mut g = Self::with_vertices(hgt * wth);
+        g.layout = Some([hgt, wth]);
+        for i in 0..hgt {
+            for j in 0..wth {
+                for [di, dj] in moves(i, j) {
+                    let (ni, nj) = (i as isize + di, j as isize + dj);
+                    let target = if ni < 0 || nj < 0 || ni >= hgt as isize || nj >= wth as isize {
+                        g.sink
+                    } else {
+                        ni as usize * wth + nj as usize
+                    };
+                    g.adj[i * wth + j].push(target);
+                }
+            }
+        }
+        g
+    }
+
+    /// Square grid, 4 neighbors: same as `Sandpile`
+    pub fn grid(hgt: usize, wth: usize) -> Self {
+        Self::lattice(hgt, wth, |_, _| vec![[-1, 0], [1, 0], [0, -1], [0, 1]])
+    }
+
+    /// Hexagonal (honeycomb) lattice, 3 neighbors, laid out as a brick wall:
+    /// all vertices are linked to their left and right neighbors, and
+    /// alternately to the vertex above or below.
+    pub fn hex(hgt: usize, wth: usize) -> Self {
+        Self::lattice(hgt, wth, |i, j| {
+            let vertical = if (i + j) % 2 == 0 { [1, 0] } else { [-1, 0] };
+            vec![[0, -1], [0, 1], vertical]
+        })
+    }
+
+    /// Triangular lattice, 6 neighbors: the square grid plus one diagonal
+    pub fn triangular(hgt: usize, wth: usize) -> Self {
+        Self::lattice(hgt, wth, |_, _| {
+            vec![[-1, 0], [1, 0], [0, -1], [0, 1], [-1, -1], [1, 1]]
+        })
+    }
+
+    /// Read a graph from a list of edges, one per line:
+    /// - `a b` is an undirected edge between vertices named `a` and `b`
+    /// - `a > b` is a directed edge from `a` to `b`
+    /// - `sink s` designates the sink
+    ///
+    /// Names are arbitrary words, repeated edges are allowed and `#` starts
+    /// a comment. Vertices are numbered in order of appearance.
+    pub fn from_file(file: &str) -> Result<Self, ParseError> {
+        Self::parse(&std::fs::read_to_string(file)?)
+    }
+
+    /// Same as `from_file`, from the contents of the file
+    pub fn parse(contents: &str) -> Result<Self, ParseError> {
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        let mut edges = Vec::new();
+        let mut sink = None;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let mut id = |name| {
+                let next = names.len();
+                *names.entry(name).or_insert(next)
+            };
+            match words[..] {
+                [] => (),
+                ["sink", s] => sink = Some(id(s)),
+                [a, ">", b] => edges.push((id(a), id(b), false)),
+                [a, b] => edges.push((id(a), id(b), true)),
+                _ => {
+                    return Err(ParseError::Invalid {
+                        line: n + 1,
+                        msg: String::from("expected `a b`, `a > b` or `sink s`"),
+                    })
+                }
+            }
+        }
+        let sink = sink.ok_or(ParseError::Invalid {
+            line: contents.lines().count(),
+            msg: String::from("no sink given"),
+        })?;
+        let mut g = Self {
+            adj: vec![Vec::new(); names.len()],
+            sink,
+            layout: None,
+        };
+        for (a, b, undirected) in edges {
+            g.adj[a].push(b);
+            if undirected {
+                g.adj[b].push(a);
+            }
+        }
+        Ok(g)
+    }
+
+    /// Number of vertices, including the sink
+    pub fn len(&self) -> usize {
+        self.adj.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.adj.is_empty()
+    }
+
+    pub fn sink(&self) -> usize {
+        self.sink
+    }
+
+    /// Number of grains needed for a vertex to topple: its out-degree
+    pub fn threshold(&self, v: usize) -> usize {
+        self.adj[v].len()
+    }
+
+    /// Check that grains can reach the sink from every vertex, otherwise
+    /// stabilization may never end
+    pub fn reaches_sink(&self) -> bool {
+        let mut rev = vec![Vec::new(); self.len()];
+        for (v, out) in self.adj.iter().enumerate() {
+            for &w in out {
+                rev[w].push(v);
+            }
+        }
+        let mut seen = vec![false; self.len()];
+        seen[self.sink] = true;
+        let mut stack = vec![self.sink];
+        while let Some(w) = stack.pop() {
+            for &v in &rev[w] {
+                if !seen[v] {
+                    seen[v] = true;
+                    stack.push(v);
+                }
+            }
+        }
+        seen.iter().all(|&s| s)
+    }
+}
+
+/// Number of grains on a vertex, colored relative to its threshold like in
+/// `Sandpile`
+#[derive(Clone, Copy)]
+struct Pile {
+    hgt: usize,
+    threshold: usize,
+}
+
+impl Colorize for Pile {
+    fn color(&self) -> Color {
+        shade(self.hgt, self.threshold)
+    }
+}
+
+/// Sandpile on an arbitrary graph: a vertex topples when it holds at least
+/// as many grains as it has outgoing edges, and sends one grain along each
+/// of them.
+pub struct GraphPile {
+    graph: Graph,
+    grains: Vec<usize>,
+    schedule: VecDeque<usize>,
+    cnt: usize,
+}
+
+impl GraphPile {
+    pub fn new(graph: Graph) -> Self {
+        Self {
+            grains: vec![0; graph.len()],
+            graph,
+            schedule: VecDeque::new(),
+            cnt: 0,
+        }
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// Number of grains on a vertex
+    pub fn get(&self, v: usize) -> usize {
+        self.grains[v]
+    }
+
+    fn is_unstable(&self, v: usize) -> bool {
+        let t = self.graph.threshold(v);
+        v != self.graph.sink && t > 0 && self.grains[v] >= t
+    }
+
+    /// Conditionally schedule a vertex for topple
+    pub fn add(&mut self, v: usize, amount: usize) {
+        if v == self.graph.sink {
+            return;
+        }
+        let was = self.is_unstable(v);
+        self.grains[v] += amount;
+        if !was && self.is_unstable(v) {
+            self.schedule.push_back(v);
+        }
+    }
+
+    /// Same as `add`, by position in the layout of a lattice
+    pub fn add_at(&mut self, i: usize, j: usize, amount: usize) {
+        let [_, wth] = self.graph.layout.expect("graph has no layout");
+        self.add(i * wth + j, amount);
+    }
+
+    /// Topple all scheduled vertices until no more topples can occur
+    pub fn stabilize(&mut self) {
+        while let Some(v) = self.schedule.pop_front() {
+            let t = self.graph.threshold(v);
+            let fall = self.grains[v] / t;
+            self.grains[v] -= fall * t;
+            for k in 0..t {
+                let w = self.graph.adj[v][k];
+                self.add(w, fall);
+            }
+            self.cnt += 1;
+        }
+    }
+
+    /// Check that no vertex can topple
+    pub fn is_stable(&self) -> bool {
+        (0..self.graph.len()).all(|v| !self.is_unstable(v))
+    }
+
+    /// Print output to file, only for graphs that have a layout
+    pub fn render(&mut self, cfg: &mut crate::Config) {
+        let [hgt, wth] = self.graph.layout.expect("graph has no layout");
+        let mut field = Canvas::new(
+            hgt,
+            wth,
+            Pile {
+                hgt: 0,
+                threshold: 1,
+            },
+        );
+        for i in 0..hgt {
+            for j in 0..wth {
+                let v = i * wth + j;
+                field[[i, j]] = Pile {
+                    hgt: self.grains[v],
+                    threshold: self.graph.threshold(v),
+                };
+            }
+        }
+        let name = cfg.frame();
+        field.render(&name);
+
+        eprint!("\rDone rendering frame {} : workload {}", name, self.cnt);
+        self.cnt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandpile::Sandpile;
+
+    #[test]
+    fn parse() {
+        let g = Graph::parse("# a triangle\na b\nb c\nc a\nc > s  # directed\nsink s\n").unwrap();
+        assert_eq!(g.len(), 4);
+        assert_eq!(g.sink(), 3);
+        assert_eq!(g.adj, vec![vec![1, 2], vec![0, 2], vec![1, 0, 3], vec![]]);
+        assert_eq!(g.threshold(2), 3);
+        assert!(g.reaches_sink());
+        // `d` cannot send its grains anywhere near the sink
+        let g = Graph::parse("a > s\nd e\nsink s").unwrap();
+        assert!(!g.reaches_sink());
+        for (text, line) in &[("a b\na > b c\nsink s", 2), ("a b\n\n", 2)] {
+            match Graph::parse(text) {
+                Err(ParseError::Invalid { line: l, .. }) => assert_eq!(l, *line),
+                Err(e) => panic!("{}", e),
+                Ok(_) => panic!("{:?} was accepted", text),
+            }
+        }
+    }
+
+    #[test]
+    fn lattices() {
+        for (g, degree) in &[
+            (Graph::grid(4, 5), 4),
+            (Graph::hex(4, 5), 3),
+            (Graph::triangular(4, 5), 6),
+        ] {
+            assert_eq!(g.len(), 21);
+            assert!((0..20).all(|v| g.threshold(v) == *degree));
+            assert!(g.reaches_sink());
+        }
+    }
+
+    #[test]
+    fn grid_matches_sandpile() {
+        let mut pile = GraphPile::new(Graph::grid(6, 7));
+        let mut sand = Sandpile::new(6, 7);
+        pile.add_at(2, 3, 300);
+        sand.add(2, 3, 300);
+        pile.add_at(5, 0, 41);
+        sand.add(5, 0, 41);
+        pile.stabilize();
+        sand.stabilize();
+        assert!(pile.is_stable());
+        let config = sand.config();
+        for i in 0..6 {
+            for j in 0..7 {
+                assert_eq!(pile.get(i * 7 + j), config.get(i, j));
+            }
+        }
+        // the sink never keeps grains
+        assert_eq!(pile.get(pile.graph().sink()), 0);
+    }
+}
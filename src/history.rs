@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+
+/// A single cell that changed state during an update
+#[derive(Clone, Copy)]
+pub struct Change<T: Copy> {
+    pub pos: [usize; 2],
+    pub old: T,
+    pub new: T,
+}
+
+/// All the cells that changed during a single update
+pub type Delta<T> = Vec<Change<T>>;
+
+/// Bounded log of the deltas between successive states of an automaton.
+///
+/// Only the changes are stored, which is much cheaper than keeping whole
+/// copies of the canvas since most cells are usually idle.
+/// Deltas that were undone are kept aside so that they can be replayed,
+/// until a new delta is recorded (which branches off from the current state).
+pub struct History<T: Copy> {
+    depth: usize,
+    past: VecDeque<Delta<T>>,
+    future: Vec<Delta<T>>,
+}
+
+impl<T: Copy> History<T> {
+    /// Keep at most `depth` deltas (0 disables the history entirely)
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            past: VecDeque::new(),
+            future: Vec::new(),
+        }
+    }
+
+    /// Recording can be skipped altogether when there is nothing to store
+    pub fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// Store a new delta, forgetting the oldest one if the buffer is full.
+    /// Any undone delta is discarded: the simulation is now on a new branch.
+    pub fn record(&mut self, delta: Delta<T>) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.future.clear();
+        if self.past.len() == self.depth {
+            self.past.pop_front();
+        }
+        self.past.push_back(delta);
+    }
+
+    /// Move the most recent delta to the redo stack and return it so that
+    /// the caller can restore the `old` states.
+    pub fn undo(&mut self) -> Option<&Delta<T>> {
+        let delta = self.past.pop_back()?;
+        self.future.push(delta);
+        self.future.last()
+    }
+
+    /// Move the most recently undone delta back into the past and return it
+    /// so that the caller can restore the `new` states.
+    pub fn redo(&mut self) -> Option<&Delta<T>> {
+        let delta = self.future.pop()?;
+        self.past.push_back(delta);
+        self.past.back()
+    }
+
+    /// Undo the most recent delta (or replay the most recently undone one if
+    /// `forward` is true) by passing each changed cell to `set` along with
+    /// the state it should be restored to.
+    /// Returns false if there is nothing to undo (or replay).
+    pub fn travel(&mut self, forward: bool, mut set: impl FnMut([usize; 2], T)) -> bool {
+        if forward {
+            match self.redo() {
+                None => false,
+                Some(delta) => {
+                    delta.iter().for_each(|c| set(c.pos, c.new));
+                    true
+                }
+            }
+        } else {
+            match self.undo() {
+                None => false,
+                Some(delta) => {
+                    delta.iter().for_each(|c| set(c.pos, c.old));
+                    true
+                }
+            }
+        }
+    }
+
+    /// Number of steps that can be undone
+    pub fn len_past(&self) -> usize {
+        self.past.len()
+    }
+
+    /// Number of steps that can be redone
+    pub fn len_future(&self) -> usize {
+        self.future.len()
+    }
+
+    /// Forget everything
+    pub fn clear(&mut self) {
+        self.past.clear();
+        self.future.clear();
+    }
+}
+
+/// An automaton that records its updates in a `History` and can travel
+/// back and forth through them.
+///
+/// Implementors only have to restore the cells (and their own counters),
+/// the navigation itself is shared.
+pub trait Rewind<T: Copy> {
+    fn history_mut(&mut self) -> &mut History<T>;
+
+    /// Undo (or replay if `forward` is true) a single update, usually
+    /// through `History::travel`
+    fn travel(&mut self, forward: bool) -> bool;
+
+    /// Remember the last `depth` updates so that they can be undone.
+    /// Any previously recorded history is lost.
+    fn keep_history(&mut self, depth: usize) {
+        *self.history_mut() = History::new(depth);
+    }
+
+    /// Restore the state before the last update.
+    /// Returns false if there is no history left to undo.
+    fn back(&mut self) -> bool {
+        self.travel(false)
+    }
+
+    /// Replay an update that was undone by `back`.
+    /// Returns false if there is nothing to replay, in which case the
+    /// automaton should be updated normally instead.
+    fn forward(&mut self) -> bool {
+        self.travel(true)
+    }
+
+    /// Undo up to `n` updates, returns how many were actually undone.
+    /// Updating the automaton afterwards starts a new branch from this
+    /// earlier state.
+    fn rewind(&mut self, n: usize) -> usize {
+        let mut k = 0;
+        while k < n && self.back() {
+            k += 1;
+        }
+        k
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(k: usize) -> Delta<usize> {
+        vec![Change {
+            pos: [k, 0],
+            old: k,
+            new: k + 1,
+        }]
+    }
+
+    #[test]
+    fn bounded() {
+        let mut h = History::new(3);
+        for k in 0..5 {
+            h.record(delta(k));
+        }
+        assert_eq!(h.len_past(), 3);
+        for k in (2..5).rev() {
+            assert_eq!(h.undo().unwrap()[0].old, k);
+        }
+        assert!(h.undo().is_none());
+        assert_eq!(h.len_future(), 3);
+        assert_eq!(h.redo().unwrap()[0].old, 2);
+        // a new delta drops the ones that were undone
+        h.record(delta(7));
+        assert_eq!((h.len_past(), h.len_future()), (2, 0));
+        assert!(h.redo().is_none());
+    }
+
+    #[test]
+    fn travel() {
+        let mut h = History::new(2);
+        h.record(delta(0));
+        h.record(delta(1));
+        let mut cells = Vec::new();
+        assert!(h.travel(false, |pos, s| cells.push((pos, s))));
+        assert!(h.travel(true, |pos, s| cells.push((pos, s))));
+        assert_eq!(cells, vec![([1, 0], 1), ([1, 0], 2)]);
+        assert!(!h.travel(true, |_, _| panic!("nothing to replay")));
+    }
+
+    #[test]
+    fn disabled() {
+        let mut h = History::new(0);
+        h.record(delta(0));
+        assert!(!h.is_enabled());
+        assert!(h.undo().is_none());
+    }
+}
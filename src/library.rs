@@ -0,0 +1,171 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::export::Format;
+use crate::lifelike::*;
+use crate::parse::*;
+
+/// Summary of a single pattern file
+pub struct Entry {
+    pub path: PathBuf,
+    /// Name from the header, or the file name without extension
+    pub name: String,
+    pub comments: Vec<String>,
+    /// `[hgt, wth]`, if the header gives it (RLE files)
+    pub size: Option<[usize; 2]>,
+    /// Rule in the `3-23` notation, if the file specifies a life-like rule
+    pub rule: Option<String>,
+}
+
+/// Index of all pattern files under a directory, such as the LifeWiki
+/// archive extracted into `data/patterns/`
+pub struct Library {
+    entries: Vec<Entry>,
+    /// Files that have a pattern extension but could not be read
+    errors: Vec<(PathBuf, ParseError)>,
+}
+
+impl Library {
+    /// Recursively index all `*.rle`, `*.cells`, `*.lif` and `*.mc` files
+    /// of a directory. Only the headers are read, the cells are read when
+    /// a pattern is used (see `place` and `open`).
+    pub fn scan(dir: &str) -> io::Result<Self> {
+        let mut lib = Self {
+            entries: Vec::new(),
+            errors: Vec::new(),
+        };
+        lib.scan_dir(Path::new(dir))?;
+        lib.entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(lib)
+    }
+
+    fn scan_dir(&mut self, dir: &Path) -> io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.scan_dir(&path)?;
+                continue;
+            }
+            let file = match path.to_str() {
+                Some(f) => f,
+                None => continue,
+            };
+            match Format::from_file(file) {
+                None | Some(Format::Txt) => continue,
+                Some(_) => (),
+            }
+            match parse_header(file) {
+                Ok(parsed) => {
+                    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file);
+                    self.entries.push(Entry {
+                        name: parsed.name.clone().unwrap_or_else(|| String::from(stem)),
+                        size: parsed.size,
+                        rule: parsed.rule,
+                        comments: parsed.comments,
+                        path,
+                    });
+                }
+                Err(e) => self.errors.push((path, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// All patterns, sorted by name
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Files whose header could not be read, with the reason why
+    pub fn errors(&self) -> &[(PathBuf, ParseError)] {
+        &self.errors
+    }
+
+    /// Find a pattern by its name or file name (case insensitive)
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        let name = name.to_lowercase();
+        self.entries.iter().find(|e| {
+            e.name.to_lowercase() == name
+                || e.path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .map(str::to_lowercase)
+                    .as_ref()
+                    == Some(&name)
+        })
+    }
+
+    /// All patterns whose name or comments contain `query` (case insensitive)
+    pub fn search(&self, query: &str) -> Vec<&Entry> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|e| {
+                e.name.to_lowercase().contains(&query)
+                    || e.comments.iter().any(|c| c.to_lowercase().contains(&query))
+            })
+            .collect()
+    }
+
+    /// Add a pattern to the game by name
+    pub fn place(
+        &self,
+        game: &mut LifeLike,
+        name: &str,
+        i0: isize,
+        j0: isize,
+        t: Transform,
+    ) -> Result<(), ParseError> {
+        game.add_from_file(self.path_of(name)?, i0, j0, t)
+    }
+
+    /// Create a game just large enough for a pattern given by name,
+    /// using the rules it specifies
+    pub fn open(&self, name: &str, margin: usize) -> Result<LifeLike, ParseError> {
+        LifeLike::from_file(self.path_of(name)?, margin)
+    }
+
+    fn path_of(&self, name: &str) -> Result<&str, ParseError> {
+        self.get(name).and_then(|e| e.path.to_str()).ok_or_else(|| {
+            ParseError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no pattern named {}", name),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let lib = Library::scan("data/malformed").unwrap();
+        let names: Vec<_> = lib.entries().iter().map(|e| e.name.as_str()).collect();
+        // files without a pattern extension are not indexed, and errors in
+        // the cells are only found when the pattern is used
+        assert_eq!(
+            names,
+            vec![
+                "Blinker",
+                "Block",
+                "Glider",
+                "bad_char",
+                "bad_char",
+                "count_before_end",
+                "multistate_ok",
+                "split_count"
+            ]
+        );
+        assert!(lib.errors().is_empty());
+        let glider = lib.get("glider").unwrap();
+        assert_eq!(glider.size, Some([3, 3]));
+        assert_eq!(glider.rule.as_deref(), Some("3-23"));
+        assert_eq!(lib.get("block").unwrap().size, None);
+        assert_eq!(lib.search("multistate").len(), 1);
+        assert!(lib.open("count_before_end", 0).is_err());
+        let game = lib.open("glider_ok", 0).unwrap();
+        assert_eq!(game.live_cells().len(), 5);
+    }
+}
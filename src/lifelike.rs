@@ -1,7 +1,11 @@
 use rand::Rng;
-use std::ascii;
+use std::io;
+use std::path::Path;
 
 use crate::canvas::*;
+use crate::export::*;
+use crate::history::*;
+use crate::parse::*;
 
 /// A cell in a life-like automata can only be alive or dead
 #[derive(Clone, Copy)]
@@ -30,10 +34,13 @@ pub struct LifeLike {
     cnt: usize,
     born: usize,
     dead: usize,
+    history: History<bool>,
+    adopt: bool,
 }
 
 impl LifeLike {
-    /// Rules are given at the initialization, and cannot be modified
+    /// Rules are given at the initialization, and are only modified
+    /// explicitly (see `set_rules` and `adopt_file_rules`)
     pub fn new(hgt: usize, wth: usize, rules: &str) -> Self {
         Self {
             rules: Rules::new(rules),
@@ -43,9 +50,47 @@ impl LifeLike {
             cnt: 0,
             born: 0,
             dead: 0,
+            history: History::new(0),
+            adopt: false,
         }
     }
 
+    /// Create a field just large enough to hold the pattern stored in a file
+    /// with `margin` cells on each side, and use the rules it specifies
+    /// (Conway's game of life if it does not).
+    /// The size declared by the file is checked against `MAX_CELLS` before
+    /// anything is allocated.
+    pub fn from_file(file: &str, margin: usize) -> Result<Self, ParseError> {
+        let parsed = parse_file(file)?;
+        let [hgt, wth] = parsed.dims();
+        let side = |n: usize| margin.checked_mul(2).and_then(|m| m.checked_add(n));
+        let (hgt, wth) = match (side(hgt), side(wth)) {
+            (Some(h), Some(w)) if h.checked_mul(w).filter(|&n| n <= MAX_CELLS).is_some() => (h, w),
+            (h, w) => {
+                return Err(ParseError::TooLarge([
+                    h.unwrap_or(usize::MAX),
+                    w.unwrap_or(usize::MAX),
+                ]))
+            }
+        };
+        let mut game = Self::new(hgt, wth, LIFE);
+        game.adopt_file_rules(true);
+        game.add_parsed(&parsed, file, margin as isize, margin as isize, T_NONE);
+        Ok(game)
+    }
+
+    /// Change the rules of a running simulation
+    pub fn set_rules(&mut self, rules: &str) {
+        self.rules = Rules::new(rules);
+    }
+
+    /// Choose what happens when a pattern file specifies other rules than the
+    /// current ones: switch to the rules of the file if `adopt` is true,
+    /// otherwise only print a warning (default).
+    pub fn adopt_file_rules(&mut self, adopt: bool) {
+        self.adopt = adopt;
+    }
+
     /// Birth cells at random all over the canvas.
     /// p is the probability for any cell of being born.
     pub fn init_rand(&mut self, p: f64) {
@@ -78,153 +123,86 @@ impl LifeLike {
     }
 
     /// Add pattern stored in an external file to the canvas.
-    /// Auto-detects file extension.
-    /// Works with `*.txt`, `*.lif`, `*.cells`, `*.rle`
-    pub fn add_from_file(&mut self, file: &str, i0: isize, j0: isize, t: Transform) {
-        let data = std::fs::read_to_string(file).unwrap();
-        let mut i = i0;
-        let mut j = j0;
-        match file.split('.').rev().next().unwrap() {
-            // get file extension
-            "txt" => {
-                for c in data.chars() {
-                    match c {
-                        '\n' => {
-                            t.newline(&mut i, &mut j, i0, j0);
-                        }
-                        'x' => {
-                            self.field.mod_idx(i, j).birth();
-                            t.next(&mut i, &mut j);
-                        }
-                        '.' => {
-                            self.field.mod_idx(i, j).kill();
-                            t.next(&mut i, &mut j);
-                        }
-                        ' ' => t.next(&mut i, &mut j),
-                        '\r' => (),
-                        c => panic!("unknown character {}", ascii::escape_default(c as u8)),
-                    }
-                }
-            }
-            "lif" => {
-                let mut it = data.chars();
-                loop {
-                    match it.next() {
-                        None => break,
-                        Some('#') => loop {
-                            match it.next() {
-                                None => break,
-                                Some('\n') => break,
-                                Some(_) => (),
-                            }
-                        },
-                        Some('\n') => {
-                            t.newline(&mut i, &mut j, i0, j0);
-                        }
-                        Some('*') => {
-                            self.field.mod_idx(i, j).birth();
-                            t.next(&mut i, &mut j);
-                        }
-                        Some('.') => {
-                            self.field.mod_idx(i, j).kill();
-                            t.next(&mut i, &mut j);
-                        }
-                        Some('\r') => (),
-                        Some(c) => panic!("unknown character {}", ascii::escape_default(c as u8)),
-                    }
-                }
+    /// Auto-detects file extension, or file contents if the extension is
+    /// missing.
+    /// Works with `*.txt`, `*.lif` (Life 1.05 and 1.06), `*.cells`, `*.rle`,
+    /// `*.mc`
+    pub fn add_from_file(
+        &mut self,
+        file: &str,
+        i0: isize,
+        j0: isize,
+        t: Transform,
+    ) -> Result<(), ParseError> {
+        let parsed = parse_file(file)?;
+        self.add_parsed(&parsed, file, i0, j0, t);
+        Ok(())
+    }
+
+    /// Add an object given by its apgcode (e.g. `xq4_153` for a glider)
+    pub fn add_apgcode(
+        &mut self,
+        code: &str,
+        i0: isize,
+        j0: isize,
+        t: Transform,
+    ) -> Result<(), ParseError> {
+        let parsed = parse_apgcode(code)?;
+        self.add_rows(&parsed.rows, i0, j0, t);
+        Ok(())
+    }
+
+    /// Add pattern stored in an external file so that its bounding box (as
+    /// declared in the header, if there is one) is centered on the canvas
+    pub fn add_centered(&mut self, file: &str, t: Transform) -> Result<(), ParseError> {
+        let parsed = parse_file(file)?;
+        // the first cell is placed relative to the center of the field
+        let [di, dj] = t.place([0, 0], parsed.dims(), Anchor::Center);
+        let i0 = self.hgt as isize / 2 + di;
+        let j0 = self.wth as isize / 2 + dj;
+        self.add_parsed(&parsed, file, i0, j0, t);
+        Ok(())
+    }
+
+    /// Add an already parsed pattern, taking into account the rules it
+    /// specifies
+    fn add_parsed(&mut self, parsed: &Parsed, file: &str, i0: isize, j0: isize, t: Transform) {
+        match (&parsed.rule_name, &parsed.rule) {
+            (Some(name), None) => {
+                eprintln!("{}: rule {} is not life-like, ignored", file, name);
             }
-            "cells" => {
-                let mut it = data.chars();
-                loop {
-                    match it.next() {
-                        None => break,
-                        Some('!') => loop {
-                            match it.next() {
-                                None => break,
-                                Some('\n') => break,
-                                Some(_) => (),
-                            }
-                        },
-                        Some('\n') => {
-                            t.newline(&mut i, &mut j, i0, j0);
-                        }
-                        Some('O') => {
-                            self.field.mod_idx(i, j).birth();
-                            t.next(&mut i, &mut j);
-                        }
-                        Some('.') => {
-                            self.field.mod_idx(i, j).kill();
-                            t.next(&mut i, &mut j);
-                        }
-                        Some('\r') => (),
-                        Some(c) => panic!(
-                            "unknown character `{}` ({})",
-                            ascii::escape_default(c as u8),
-                            c as u32
-                        ),
-                    }
+            (Some(name), Some(rule)) if Rules::new(rule) != self.rules => {
+                if self.adopt {
+                    eprintln!("{}: switching to rule {}", file, name);
+                    self.set_rules(rule);
+                } else {
+                    eprintln!(
+                        "{}: written for rule {} but simulation uses {}",
+                        file,
+                        name,
+                        self.rules.to_bs()
+                    );
                 }
             }
-            "rle" => {
-                let mut it = data.chars();
-                let mut cnt = 0;
-                loop {
-                    match it.next() {
-                        None => break,
-                        Some('#') | Some('x') => loop {
-                            // '#' is a comment,
-                            // 'x' marks the start of an 'x = {}, y = {}' that this implementation chooses to ignore
-                            match it.next() {
-                                None => break,
-                                Some('\n') => break,
-                                Some(_) => (),
-                            }
-                        },
-                        Some('$') => {
-                            if cnt == 0 {
-                                cnt = 1;
-                            }
-                            for _ in 0..cnt {
-                                t.newline(&mut i, &mut j, i0, j0);
-                            }
-                            cnt = 0;
-                        }
-                        Some('o') => {
-                            if cnt == 0 {
-                                cnt = 1;
-                            }
-                            for _ in 0..cnt {
-                                self.field.mod_idx(i, j).birth();
-                                t.next(&mut i, &mut j);
-                            }
-                            cnt = 0;
-                        }
-                        Some('b') => {
-                            if cnt == 0 {
-                                cnt = 1;
-                            }
-                            for _ in 0..cnt {
-                                self.field.mod_idx(i, j).kill();
-                                t.next(&mut i, &mut j);
-                            }
-                            cnt = 0;
-                        }
-                        Some(d @ '0'..='9') => {
-                            cnt = cnt * 10 + d.to_digit(10).unwrap();
-                        }
-                        Some('!') => break,
-                        Some('\r') => (),
-                        Some('\n') => (),
-                        Some(c) => panic!(
-                            "unknown character `{}` ({})",
-                            ascii::escape_default(c as u8),
-                            c as u32
-                        ),
-                    }
+            _ => (),
+        }
+        self.add_rows(&parsed.rows, i0, j0, t);
+    }
+
+    /// Apply a parsed pattern to the canvas, starting at `(i0, j0)`
+    pub fn add_rows(&mut self, rows: &[Vec<Instr>], i0: isize, j0: isize, t: Transform) {
+        let mut i = i0;
+        let mut j = j0;
+        for row in rows {
+            for instr in row {
+                match instr {
+                    Some(true) => self.field.mod_idx(i, j).birth(),
+                    Some(false) => self.field.mod_idx(i, j).kill(),
+                    None => (),
                 }
+                t.next(&mut i, &mut j);
             }
-            ext => panic!("{} is not recognized as a valid extension", ext),
+            t.newline(&mut i, &mut j, i0, j0);
         }
         self.update();
     }
@@ -233,13 +211,24 @@ impl LifeLike {
     pub fn update(&mut self) {
         self.born = 0;
         self.dead = 0;
+        let mut delta = Vec::new();
         for i in 0..self.hgt {
             for j in 0..self.wth {
+                let old = self.field[[i, j]].curr;
                 self.field[[i, j]].update(&mut self.born, &mut self.dead);
+                let new = self.field[[i, j]].curr;
+                if old != new && self.history.is_enabled() {
+                    delta.push(Change {
+                        pos: [i, j],
+                        old,
+                        new,
+                    });
+                }
             }
         }
         self.cnt += self.born;
         self.cnt -= self.dead;
+        self.history.record(delta);
     }
 
     /// 2D Array access with looping around the edges.
@@ -333,6 +322,114 @@ impl LifeLike {
         self.update();
     }
 
+    /// Birth all given cells, offset by `(i0, j0)` (with wrapping)
+    pub fn add_cells(&mut self, cells: &[[isize; 2]], i0: isize, j0: isize) {
+        for [i, j] in cells {
+            self.field.mod_idx(i0 + i, j0 + j).birth();
+        }
+        self.update();
+    }
+
+    /// Number of cells currently alive
+    pub fn population(&self) -> usize {
+        self.cnt
+    }
+
+    /// Dimensions of the field as `[hgt, wth]`
+    pub fn dims(&self) -> [usize; 2] {
+        [self.hgt, self.wth]
+    }
+
+    /// Coordinates of all live cells, in reading order
+    pub fn live_cells(&self) -> Vec<[usize; 2]> {
+        let mut v = Vec::with_capacity(self.cnt);
+        for i in 0..self.hgt {
+            for j in 0..self.wth {
+                if self.field[[i, j]].is_alive() {
+                    v.push([i, j]);
+                }
+            }
+        }
+        v
+    }
+
+    /// Smallest rectangle `([i0, j0], [hgt, wth])` that contains all live
+    /// cells, if there are any
+    pub fn bounding_box(&self) -> Option<([usize; 2], [usize; 2])> {
+        let cells = self.live_cells();
+        let imin = cells.iter().map(|c| c[0]).min()?;
+        let imax = cells.iter().map(|c| c[0]).max()?;
+        let jmin = cells.iter().map(|c| c[1]).min()?;
+        let jmax = cells.iter().map(|c| c[1]).max()?;
+        Some(([imin, jmin], [imax - imin + 1, jmax - jmin + 1]))
+    }
+
+    /// Copy the states of a rectangle of size `[hgt, wth]` starting at
+    /// `[i0, j0]` (with wrapping)
+    pub fn region(&mut self, [i0, j0]: [usize; 2], [hgt, wth]: [usize; 2]) -> Region {
+        (0..hgt)
+            .map(|i| {
+                (0..wth)
+                    .map(|j| {
+                        self.field
+                            .mod_idx((i0 + i) as isize, (j0 + j) as isize)
+                            .is_alive()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Write a rectangle of the field to a file in the given format.
+    /// Macrocell files can only be read.
+    pub fn save_region(
+        &mut self,
+        file: &str,
+        fmt: Format,
+        start: [usize; 2],
+        size: [usize; 2],
+    ) -> io::Result<()> {
+        let region = self.region(start, size);
+        let contents = match fmt {
+            Format::Rle => to_rle(&region, &self.rules.to_bs()),
+            Format::Cells => {
+                let name = Path::new(file).file_stem().and_then(|s| s.to_str());
+                to_cells(&region, name.unwrap_or(file))
+            }
+            Format::Life105 => to_life105(&region, &self.rules.to_sb()),
+            Format::Life106 => to_life106(&region),
+            Format::Txt => to_txt(&region),
+            Format::Macrocell => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: macrocell files can only be read", file),
+                ))
+            }
+        };
+        write(file, &contents)
+    }
+
+    /// Write the smallest area containing all live cells to a file in the
+    /// given format (e.g. Life 1.06, which shares the `*.lif` extension with
+    /// Life 1.05)
+    pub fn save_as(&mut self, file: &str, fmt: Format) -> io::Result<()> {
+        let (start, size) = self.bounding_box().unwrap_or(([0, 0], [0, 0]));
+        self.save_region(file, fmt, start, size)
+    }
+
+    /// Write the smallest area containing all live cells to a file.
+    /// Auto-detects file extension (`*.lif` is written as Life 1.05),
+    /// the result can be read back with `add_from_file`.
+    pub fn save_to_file(&mut self, file: &str) -> io::Result<()> {
+        let fmt = Format::from_file(file).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not have a recognized extension", file),
+            )
+        })?;
+        self.save_as(file, fmt)
+    }
+
     /// Output current state to a file
     pub fn render(&mut self, cfg: &mut crate::Config) {
         let name = cfg.frame();
@@ -345,6 +442,24 @@ impl LifeLike {
     }
 }
 
+impl Rewind<bool> for LifeLike {
+    fn history_mut(&mut self) -> &mut History<bool> {
+        &mut self.history
+    }
+
+    fn travel(&mut self, forward: bool) -> bool {
+        self.born = 0;
+        self.dead = 0;
+        let (field, born, dead) = (&mut self.field, &mut self.born, &mut self.dead);
+        let done = self
+            .history
+            .travel(forward, |pos, s| field[pos].set(s, born, dead));
+        self.cnt += self.born;
+        self.cnt -= self.dead;
+        done
+    }
+}
+
 impl Cell {
     /// All cells are created dead by default.
     pub fn new() -> Self {
@@ -380,12 +495,18 @@ impl Cell {
     pub fn is_alive(self) -> bool {
         self.curr
     }
+
+    /// Overwrite both current and next state (used to travel through history)
+    pub fn set(&mut self, state: bool, born: &mut usize, dead: &mut usize) {
+        self.succ = state;
+        self.update(born, dead);
+    }
 }
 
 /// Rules indicate for both possible states and for each possible
 /// number of live neighbors whether or not the cell should be alive for the
 /// next iteration.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 struct Rules {
     /// B: Born; S: Survive
     b: [bool; 9],
@@ -417,8 +538,27 @@ impl Rules {
         }
         r
     }
+
+    /// Neighbor counts for which the rule is true, as a string of digits
+    fn digits(r: &[bool; 9]) -> String {
+        (0..9).filter(|&n| r[n]).map(|n| n.to_string()).collect()
+    }
+
+    /// Standard notation for rules: `B3/S23`
+    pub fn to_bs(self) -> String {
+        format!("B{}/S{}", Self::digits(&self.b), Self::digits(&self.s))
+    }
+
+    /// Legacy notation for rules, survive first: `23/3`
+    pub fn to_sb(self) -> String {
+        format!("{}/{}", Self::digits(&self.s), Self::digits(&self.b))
+    }
 }
 
+/// Largest field that `LifeLike::from_file` agrees to create, since its
+/// size comes from the header of the file
+pub const MAX_CELLS: usize = 1 << 28;
+
 pub const LIFE: &str = "3-23";
 pub const REPLICATOR: &str = "1357-1357";
 pub const SEEDS: &str = "2-";
@@ -431,7 +571,8 @@ pub const DAYNIGHT: &str = "3678-34678";
 pub const MORLEY: &str = "368-245";
 pub const ANNEAL: &str = "4678-35678";
 
-/// Possible rotations of a pattern
+/// Possible rotations of a pattern (`Left` is counterclockwise)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Rotate {
     None,
     Left,
@@ -439,73 +580,152 @@ pub enum Rotate {
     Double,
 }
 
+impl Rotate {
+    /// Number of quarter turns counterclockwise
+    fn quarters(self) -> u8 {
+        match self {
+            Rotate::None => 0,
+            Rotate::Left => 1,
+            Rotate::Double => 2,
+            Rotate::Right => 3,
+        }
+    }
+
+    fn from_quarters(k: u8) -> Self {
+        match k % 4 {
+            0 => Rotate::None,
+            1 => Rotate::Left,
+            2 => Rotate::Double,
+            _ => Rotate::Right,
+        }
+    }
+}
+
+/// Which point of a transformed pattern is placed at the requested position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    /// The first cell of the pattern, wherever the transformation sends it
+    /// (this is how files are read)
+    Origin,
+    /// Top-left corner of the bounding box after the transformation
+    TopLeft,
+    /// Center of the bounding box
+    Center,
+}
+
 /// All transformations of a pattern are a combination of a rotation and
-/// a symmetry
+/// a symmetry: the rotation is applied first, then the pattern is mirrored
+/// left to right.
+///
+/// These form the dihedral group D4: transformations can be composed and
+/// inverted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Transform {
     rot: Rotate,
     mirror: bool,
 }
 
 impl Transform {
-    /// Calculate index of next cell when staying on the same line
-    pub fn next(&self, i: &mut isize, j: &mut isize) {
+    pub fn new(rot: Rotate, mirror: bool) -> Self {
+        Self { rot, mirror }
+    }
+
+    /// Position of the cell at line `i` and column `j` of the pattern, relative
+    /// to the position of the first cell, after applying the transformation
+    pub fn apply(&self, i: isize, j: isize) -> [isize; 2] {
+        let [i, j] = match self.rot {
+            Rotate::None => [i, j],
+            Rotate::Left => [-j, i],
+            Rotate::Double => [-i, -j],
+            Rotate::Right => [j, -i],
+        };
         if self.mirror {
-            match self.rot {
-                Rotate::None => *j -= 1,
-                Rotate::Left => *i -= 1,
-                Rotate::Right => *i += 1,
-                Rotate::Double => *j += 1,
-            }
+            [i, -j]
+        } else {
+            [i, j]
+        }
+    }
+
+    /// Apply `self` then `other`
+    pub fn then(&self, other: &Transform) -> Transform {
+        // mirroring reverses the direction of the rotations that follow it
+        let k = if self.mirror {
+            self.rot.quarters() + 4 - other.rot.quarters()
+        } else {
+            self.rot.quarters() + other.rot.quarters()
+        };
+        Transform {
+            rot: Rotate::from_quarters(k),
+            mirror: self.mirror != other.mirror,
+        }
+    }
+
+    /// The transformation that cancels this one
+    pub fn inverse(&self) -> Transform {
+        if self.mirror {
+            // all symmetries are their own inverse
+            *self
         } else {
-            match self.rot {
-                Rotate::None => *j += 1,
-                Rotate::Left => *i -= 1,
-                Rotate::Right => *i += 1,
-                Rotate::Double => *j -= 1,
+            Transform {
+                rot: Rotate::from_quarters(4 - self.rot.quarters()),
+                mirror: false,
             }
         }
     }
 
+    /// Dimensions `[hgt, wth]` of a pattern once transformed
+    pub fn dims(&self, [hgt, wth]: [usize; 2]) -> [usize; 2] {
+        match self.rot {
+            Rotate::None | Rotate::Double => [hgt, wth],
+            Rotate::Left | Rotate::Right => [wth, hgt],
+        }
+    }
+
+    /// Position of the cell at line `i` and column `j` of a pattern of size
+    /// `[hgt, wth]`, relative to the anchor, after applying the transformation
+    pub fn place(&self, [i, j]: [isize; 2], [hgt, wth]: [usize; 2], anchor: Anchor) -> [isize; 2] {
+        let [ti, tj] = self.apply(i, j);
+        if anchor == Anchor::Origin {
+            return [ti, tj];
+        }
+        // the corner of the bounding box that ends up at the top-left
+        let (h, w) = (hgt.max(1) as isize - 1, wth.max(1) as isize - 1);
+        let corners = [[0, 0], [0, w], [h, 0], [h, w]];
+        let imin = corners
+            .iter()
+            .map(|&[i, j]| self.apply(i, j)[0])
+            .min()
+            .unwrap();
+        let jmin = corners
+            .iter()
+            .map(|&[i, j]| self.apply(i, j)[1])
+            .min()
+            .unwrap();
+        let [ti, tj] = [ti - imin, tj - jmin];
+        if anchor == Anchor::TopLeft {
+            return [ti, tj];
+        }
+        let [th, tw] = self.dims([hgt, wth]);
+        [ti - th as isize / 2, tj - tw as isize / 2]
+    }
+
+    /// Calculate index of next cell when staying on the same line
+    pub fn next(&self, i: &mut isize, j: &mut isize) {
+        let [di, dj] = self.apply(0, 1);
+        *i += di;
+        *j += dj;
+    }
+
     /// Calculate index of next cell when a newline is added
     pub fn newline(&self, i: &mut isize, j: &mut isize, i0: isize, j0: isize) {
-        if self.mirror {
-            match self.rot {
-                Rotate::None => {
-                    *i += 1;
-                    *j = j0;
-                }
-                Rotate::Left => {
-                    *j -= 1;
-                    *i = i0;
-                }
-                Rotate::Right => {
-                    *j += 1;
-                    *i = i0;
-                }
-                Rotate::Double => {
-                    *i -= 1;
-                    *j = j0;
-                }
-            }
+        let [di, dj] = self.apply(1, 0);
+        // go back to the start of the line, then move to the next one
+        if di == 0 {
+            *i = i0;
+            *j += dj;
         } else {
-            match self.rot {
-                Rotate::None => {
-                    *i += 1;
-                    *j = j0;
-                }
-                Rotate::Left => {
-                    *j += 1;
-                    *i = i0;
-                }
-                Rotate::Right => {
-                    *j -= 1;
-                    *i = i0;
-                }
-                Rotate::Double => {
-                    *i -= 1;
-                    *j = j0;
-                }
-            }
+            *i += di;
+            *j = j0;
         }
     }
 }
@@ -542,3 +762,143 @@ pub const T_DB_SYM: Transform = Transform {
     rot: Rotate::Double,
     mirror: true,
 };
+
+/// All 8 transformations
+pub const T_ALL: [Transform; 8] = [
+    T_NONE, T_LT, T_RT, T_DB, T_NONE_SYM, T_LT_SYM, T_RT_SYM, T_DB_SYM,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    fn from_text(lines: &[&str]) -> Vec<Vec<Instr>> {
+        lines
+            .iter()
+            .map(|line| line.chars().map(|c| Some(c == '#')).collect())
+            .collect()
+    }
+
+    /// R-pentomino, which has no symmetry at all
+    fn rows() -> Vec<Vec<Instr>> {
+        from_text(&[".##", "##.", ".#."])
+    }
+
+    #[test]
+    fn back_and_forward() {
+        let mut game = LifeLike::new(30, 30, LIFE);
+        game.keep_history(10);
+        game.add_rows(&rows(), 12, 12, T_NONE);
+        let mut states = vec![Pattern::from_game(&game)];
+        for _ in 0..15 {
+            game.next();
+            states.push(Pattern::from_game(&game));
+        }
+        for k in (5..15).rev() {
+            assert!(game.back());
+            assert_eq!(Pattern::from_game(&game), states[k]);
+            assert_eq!(game.live_cells().len(), game.cnt);
+        }
+        // only 10 updates are remembered
+        assert!(!game.back());
+        for state in &states[6..16] {
+            assert!(game.forward());
+            assert_eq!(&Pattern::from_game(&game), state);
+        }
+        assert!(!game.forward());
+        assert_eq!(game.rewind(3), 3);
+        game.next();
+        assert_eq!(Pattern::from_game(&game), states[13]);
+        assert!(!game.forward());
+    }
+
+    #[test]
+    fn file_rules_and_size() {
+        let file = std::env::temp_dir().join("lifelike_file_rules.rle");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, "x = 5, y = 4, rule = B36/S23\n3o!\n").unwrap();
+        let game = LifeLike::from_file(file, 2).unwrap();
+        assert_eq!((game.hgt, game.wth), (8, 9));
+        assert!(game.rules == Rules::new("36-23"));
+        assert_eq!(game.live_cells(), vec![[2, 2], [2, 3], [2, 4]]);
+        // rules are only switched when asked to
+        let mut game = LifeLike::new(11, 11, LIFE);
+        game.add_centered(file, T_NONE).unwrap();
+        assert!(game.rules == Rules::new(LIFE));
+        // the declared 4x5 box is centered, not the single line of cells
+        assert_eq!(game.live_cells(), vec![[3, 3], [3, 4], [3, 5]]);
+        game.adopt_file_rules(true);
+        game.add_from_file(file, 0, 0, T_NONE).unwrap();
+        assert!(game.rules == Rules::new("36-23"));
+        // the field is not allocated from a header that claims a huge size
+        std::fs::write(file, "x = 1000000000000, y = 1\n3o!\n").unwrap();
+        match LifeLike::from_file(file, 2) {
+            Err(ParseError::TooLarge(size)) => assert_eq!(size, [5, 1000000000004]),
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("allocated a huge field"),
+        }
+        std::fs::write(file, "x = 3, y = 1\n3o!\n").unwrap();
+        assert!(matches!(
+            LifeLike::from_file(file, usize::MAX),
+            Err(ParseError::TooLarge(_))
+        ));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn transforms_are_distinct() {
+        let pat = Pattern::from_rows(&rows());
+        let images: Vec<_> = T_ALL.iter().map(|t| pat.transform(t)).collect();
+        let expected = |lines: &[&str]| Pattern::from_rows(&from_text(lines));
+        // in the order of `T_ALL`
+        let drawings = [
+            [".##", "##.", ".#."],
+            ["#..", "###", ".#."],
+            [".#.", "###", "..#"],
+            [".#.", ".##", "##."],
+            ["##.", ".##", ".#."],
+            ["..#", "###", ".#."],
+            [".#.", "###", "#.."],
+            [".#.", "##.", ".##"],
+        ];
+        for (k, lines) in drawings.iter().enumerate() {
+            assert_eq!(images[k], expected(lines), "{:?}", T_ALL[k]);
+        }
+        for a in 0..8 {
+            for b in a + 1..8 {
+                assert_ne!(images[a], images[b], "{:?} and {:?}", T_ALL[a], T_ALL[b]);
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_compose_and_invert() {
+        let points = [[0, 0], [1, 2], [-3, 1], [2, -5]];
+        for a in &T_ALL {
+            assert_eq!(a.then(&a.inverse()), T_NONE);
+            assert_eq!(a.inverse().then(a), T_NONE);
+            for b in &T_ALL {
+                for &[i, j] in &points {
+                    let [ai, aj] = a.apply(i, j);
+                    assert_eq!(a.then(b).apply(i, j), b.apply(ai, aj));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn add_rows_matches_pattern() {
+        let pat = Pattern::from_rows(&rows());
+        for t in &T_ALL {
+            let mut game = LifeLike::new(20, 20, LIFE);
+            game.add_rows(&rows(), 10, 10, *t);
+            assert_eq!(
+                Pattern::from_game(&game).normalize(),
+                pat.transform(t).normalize(),
+                "{:?}",
+                t
+            );
+        }
+    }
+}
@@ -3,18 +3,37 @@
 use std::fs;
 use std::process::Command;
 
+mod avalanche;
 mod brain;
 mod canvas;
+mod census;
+mod export;
+mod graph;
+mod history;
+mod library;
 mod lifelike;
+mod parse;
+mod pattern;
+mod period;
 mod sandpile;
+mod scan;
+mod source;
 mod turmite;
+mod zhang;
 
 use brain::*;
 use lifelike::*;
 use sandpile::*;
+use source::*;
 use turmite::*;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("scan") {
+        scan::main(&args[2..]);
+        return;
+    }
+
     let name = String::from("breeder");
     let algo = Automaton::LifeLike(LIFE);
     let mut cfg = Config::new(algo, name, 25);
@@ -28,19 +47,28 @@ fn render(cfg: &mut Config) {
     match &cfg.algo {
         Automaton::Sandpile => {
             let mut pile = Sandpile::new(201, 201);
+            pile.set_sources(
+                Sources::parse(
+                    "drip 100 100 5\n\
+                     drip 110 110 10\n\
+                     drip 120 120 20\n\
+                     drip 90 90 10\n\
+                     drip 80 80 20",
+                    0,
+                )
+                .unwrap_or_else(|e| panic!("{}", e)),
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+            pile.set_palette(Palette::AVALANCHE);
             for _ in 0..1000 {
-                pile.render(cfg);
-                pile.add(100, 100, 5);
-                pile.add(110, 110, 10);
-                pile.add(120, 120, 20);
-                pile.add(90, 90, 10);
-                pile.add(80, 80, 20);
-                pile.stabilize();
+                pile.feed();
+                pile.stabilize_render(cfg, Step::Wave);
             }
         }
         Automaton::LifeLike(rules) => {
             let mut game = LifeLike::new(1000, 2000, &rules);
-            game.add_from_file("data/patterns/p69060p5h2v0gun.rle", 0, 0, T_NONE);
+            game.add_from_file("data/patterns/p69060p5h2v0gun.rle", 0, 0, T_NONE)
+                .unwrap_or_else(|e| panic!("{}", e));
             for _ in 0..2000 {
                 game.render(cfg);
                 game.next();
@@ -55,7 +83,7 @@ fn render(cfg: &mut Config) {
             }
         }
         Automaton::Turmite(rules) => {
-            let mut mound = Mound::new(900, 900, rules);
+            let mut mound = Mound::new(900, 900, *rules);
             for _ in 0..50 {
                 mound.add_rand([449, 452], [449, 452], None);
             }
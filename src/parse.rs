@@ -0,0 +1,895 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::export::Format;
+
+/// What to do with a single cell of a pattern:
+/// `Some(true)` births it, `Some(false)` kills it and `None` leaves it as is.
+pub type Instr = Option<bool>;
+
+/// A pattern as it was read from a file, line by line
+pub type Rows = Vec<Vec<Instr>>;
+
+/// A pattern along with the information found in its header
+pub struct Parsed {
+    /// Name of the pattern (`#N` or `!Name:` line)
+    pub name: Option<String>,
+    /// Description, author, links, ...
+    pub comments: Vec<String>,
+    /// `[hgt, wth]` as declared by the file
+    pub size: Option<[usize; 2]>,
+    /// Rule as written in the file
+    pub rule_name: Option<String>,
+    /// Same rule in the `3-23` notation of `LifeLike::new`, if it is a
+    /// life-like rule
+    pub rule: Option<String>,
+    pub rows: Rows,
+}
+
+impl Parsed {
+    fn new(rows: Rows) -> Self {
+        Self {
+            name: None,
+            comments: Vec::new(),
+            size: None,
+            rule_name: None,
+            rule: None,
+            rows,
+        }
+    }
+
+    fn with_rule(mut self, name: &str) -> Self {
+        self.rule = parse_rule(name);
+        self.rule_name = Some(String::from(name));
+        self
+    }
+
+    /// Dimensions `[hgt, wth]` of the pattern: those of the header if there is
+    /// one, otherwise those of the rows that were read
+    pub fn dims(&self) -> [usize; 2] {
+        self.size.unwrap_or_else(|| {
+            [
+                self.rows.len(),
+                self.rows.iter().map(|r| r.len()).max().unwrap_or(0),
+            ]
+        })
+    }
+}
+
+/// Everything that can go wrong while reading a pattern
+#[derive(Debug)]
+pub enum ParseError {
+    /// The file could not be read
+    Io(io::Error),
+    /// Neither the extension nor the contents tell what the format is
+    UnknownFormat(String),
+    /// A character that has no meaning at this position
+    /// (`line` and `col` start at 1)
+    Unexpected { line: usize, col: usize, c: char },
+    /// A run count that is not followed by a cell or a newline
+    DanglingCount { line: usize, col: usize },
+    /// A header line that could not be understood
+    Header { line: usize, msg: String },
+    /// The pattern is too large to be loaded (`[hgt, wth]` including the
+    /// margins)
+    TooLarge([usize; 2]),
+    /// A line that is well-formed but does not make sense
+    /// (e.g. a macrocell node that refers to a node not yet defined)
+    Invalid { line: usize, msg: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "could not read file: {}", e),
+            ParseError::UnknownFormat(file) => write!(f, "could not guess the format of {}", file),
+            ParseError::Unexpected { line, col, c } => write!(
+                f,
+                "unexpected character `{}` (U+{:04X}) at {}:{}",
+                c.escape_default(),
+                *c as u32,
+                line,
+                col
+            ),
+            ParseError::DanglingCount { line, col } => {
+                write!(f, "run count not followed by a state at {}:{}", line, col)
+            }
+            ParseError::Header { line, msg } => {
+                write!(f, "invalid header at line {}: {}", line, msg)
+            }
+            ParseError::TooLarge([hgt, wth]) => {
+                write!(f, "a {}x{} field is too large to be allocated", hgt, wth)
+            }
+            ParseError::Invalid { line, msg } => write!(f, "invalid line {}: {}", line, msg),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// Guess the format of a pattern from its contents
+pub fn sniff(data: &str) -> Option<Format> {
+    let mut lines = data.lines().map(str::trim_end).filter(|l| !l.is_empty());
+    let first = lines.clone().next()?;
+    if first.starts_with("#Life 1.05") {
+        return Some(Format::Life105);
+    }
+    if first.starts_with("#Life 1.06") {
+        return Some(Format::Life106);
+    }
+    if first.starts_with("[M2]") {
+        return Some(Format::Macrocell);
+    }
+    if first.starts_with('!') {
+        return Some(Format::Cells);
+    }
+    // skip RLE/Life 1.05 comments
+    if let Some(l) = lines.find(|l| !l.starts_with('#')) {
+        let l = l.trim_start();
+        if l.starts_with('x') && l.contains('=') {
+            return Some(Format::Rle);
+        }
+        if l.chars().all(|c| c == '.' || c == 'O') {
+            return Some(Format::Cells);
+        }
+        if l.chars().all(|c| c == '.' || c == '*') {
+            return Some(Format::Life105);
+        }
+        if l.chars().all(|c| c == '.' || c == 'x' || c == ' ') {
+            return Some(Format::Txt);
+        }
+    }
+    None
+}
+
+/// Read a pattern file, using its extension to determine the format or
+/// its contents if the extension is missing or unknown.
+/// `*.lif` files are checked for a `#Life 1.06` header.
+pub fn parse_file(file: &str) -> Result<Parsed, ParseError> {
+    let data = std::fs::read_to_string(file)?;
+    let fmt = match Format::from_file(file) {
+        Some(Format::Life105) => sniff(&data).unwrap_or(Format::Life105),
+        Some(fmt) => fmt,
+        None => sniff(&data).ok_or_else(|| ParseError::UnknownFormat(String::from(file)))?,
+    };
+    parse_str(&data, fmt)
+}
+
+/// Read only the header of a pattern file (name, comments, rule, and the
+/// size for RLE files), without going through the cells: `rows` is empty.
+/// The format is guessed as in `parse_file`.
+pub fn parse_header(file: &str) -> Result<Parsed, ParseError> {
+    let mut lines = Vec::new();
+    for line in BufReader::new(File::open(file)?).lines() {
+        let line = line?;
+        let l = line.trim();
+        let header = l.is_empty() || l.starts_with('#') || l.starts_with('!') || l == "[M2]";
+        lines.push(line);
+        // the first line of the body is kept to guess the format
+        if !header {
+            break;
+        }
+    }
+    let data = lines.join("\n");
+    let fmt = match Format::from_file(file) {
+        Some(Format::Life105) => sniff(&data).unwrap_or(Format::Life105),
+        Some(fmt) => fmt,
+        None => sniff(&data).ok_or_else(|| ParseError::UnknownFormat(String::from(file)))?,
+    };
+    let mut parsed = match fmt {
+        Format::Rle => {
+            let (n, line) = lines
+                .iter()
+                .enumerate()
+                .find(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'))
+                .ok_or(ParseError::Header {
+                    line: lines.len(),
+                    msg: String::from("missing `x = {}, y = {}` line"),
+                })?;
+            parse_rle_header(line, n)?
+        }
+        Format::Life105 => life105_rule(Parsed::new(Vec::new()), &data),
+        _ => Parsed::new(Vec::new()),
+    };
+    let (name, comments) = metadata(&data, fmt);
+    parsed.name = name;
+    parsed.comments = comments;
+    Ok(parsed)
+}
+
+/// Read a pattern in a known format
+pub fn parse_str(data: &str, fmt: Format) -> Result<Parsed, ParseError> {
+    let mut parsed = parse_body(data, fmt)?;
+    let (name, comments) = metadata(data, fmt);
+    parsed.name = name;
+    parsed.comments = comments;
+    Ok(parsed)
+}
+
+/// Collect the name and comments of a pattern.
+///
+/// - RLE and macrocell: `#N` for the name, `#C`, `#c`, `#O`, `#D` for comments
+/// - plaintext: `!Name:` for the name, other `!` lines for comments
+/// - Life 1.05 and 1.06: `#D` and `#C` for comments
+fn metadata(data: &str, fmt: Format) -> (Option<String>, Vec<String>) {
+    let mut name = None;
+    let mut comments = Vec::new();
+    for line in data.lines().map(str::trim_end) {
+        match fmt {
+            Format::Rle | Format::Macrocell => {
+                if let Some(n) = line.strip_prefix("#N") {
+                    name = Some(String::from(n.trim()));
+                } else if ["#C", "#c", "#O", "#D"].iter().any(|p| line.starts_with(p)) {
+                    comments.push(String::from(line[2..].trim()));
+                }
+            }
+            Format::Cells => {
+                if let Some(n) = line.strip_prefix("!Name:") {
+                    name = Some(String::from(n.trim()));
+                } else if let Some(c) = line.strip_prefix('!') {
+                    comments.push(String::from(c.trim()));
+                }
+            }
+            Format::Life105 | Format::Life106 => {
+                if line.starts_with("#D") || line.starts_with("#C") {
+                    comments.push(String::from(line[2..].trim()));
+                }
+            }
+            Format::Txt => (),
+        }
+    }
+    (name.filter(|n| !n.is_empty()), comments)
+}
+
+/// Read the cells and header of a pattern
+fn parse_body(data: &str, fmt: Format) -> Result<Parsed, ParseError> {
+    Ok(match fmt {
+        Format::Txt => Parsed::new(parse_grid(data, None, |c| match c {
+            'x' => Some(Some(true)),
+            '.' => Some(Some(false)),
+            ' ' => Some(None),
+            _ => None,
+        })?),
+        Format::Life105 => life105_rule(
+            Parsed::new(parse_grid(data, Some('#'), |c| match c {
+                '*' => Some(Some(true)),
+                '.' => Some(Some(false)),
+                _ => None,
+            })?),
+            data,
+        ),
+        Format::Cells => Parsed::new(parse_grid(data, Some('!'), |c| match c {
+            'O' | '*' => Some(Some(true)),
+            '.' => Some(Some(false)),
+            _ => None,
+        })?),
+        Format::Rle => parse_rle(data)?,
+        Format::Life106 => parse_life106(data)?,
+        Format::Macrocell => parse_macrocell(data)?,
+    })
+}
+
+/// Rule of a Life 1.05 file: `#N` is the normal game of life, `#R` gives
+/// the rule
+fn life105_rule(parsed: Parsed, data: &str) -> Parsed {
+    match data
+        .lines()
+        .find(|l| l.starts_with("#N") || l.starts_with("#R"))
+    {
+        Some(l) if l.starts_with("#N") => parsed.with_rule("B3/S23"),
+        Some(l) => parsed.with_rule(l[2..].trim()),
+        None => parsed,
+    }
+}
+
+/// Convert a rule written as `B3/S23` (in any case and order) or in the
+/// legacy `23/3` notation (survive first) to the `3-23` notation.
+/// The topology of Golly's bounded grids (`:T10,10`, `:P20,20`, ...) is
+/// ignored.
+/// Returns `None` for anything that is not a life-like rule.
+pub fn parse_rule(s: &str) -> Option<String> {
+    let s = s.split(':').next().unwrap_or("").trim().to_uppercase();
+    let parts: Vec<_> = s.split('/').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    let digits = |p: &str| {
+        if p.chars().all(|c| ('0'..='8').contains(&c)) {
+            Some(String::from(p))
+        } else {
+            None
+        }
+    };
+    let (b, s) = match (parts[0].chars().next(), parts[1].chars().next()) {
+        (Some('B'), Some('S')) => (digits(&parts[0][1..])?, digits(&parts[1][1..])?),
+        (Some('S'), Some('B')) => (digits(&parts[1][1..])?, digits(&parts[0][1..])?),
+        _ => (digits(parts[1])?, digits(parts[0])?),
+    };
+    Some(format!("{}-{}", b, s))
+}
+
+/// Read the `x = {}, y = {}, rule = {}` line of a RLE file
+fn parse_rle_header(line: &str, n: usize) -> Result<Parsed, ParseError> {
+    let err = |msg: String| ParseError::Header { line: n + 1, msg };
+    let mut parsed = Parsed::new(Vec::new());
+    let (mut x, mut y) = (None, None);
+    let mut items = line.split(',');
+    while let Some(item) = items.next() {
+        let kv: Vec<_> = item.splitn(2, '=').map(str::trim).collect();
+        if kv.len() != 2 {
+            return Err(err(format!(
+                "`{}` is not of the form `key = value`",
+                item.trim()
+            )));
+        }
+        let size = || {
+            kv[1]
+                .parse::<usize>()
+                .map_err(|_| err(format!("`{}` is not a valid size", kv[1])))
+        };
+        match kv[0] {
+            "x" => x = Some(size()?),
+            "y" => y = Some(size()?),
+            // the rule goes to the end of the line, it may contain commas
+            // (e.g. the size of a bounded grid in `B3/S23:T10,10`)
+            "rule" => {
+                let rule: Vec<_> = std::iter::once(kv[1]).chain(items.by_ref()).collect();
+                parsed = parsed.with_rule(rule.join(",").trim());
+            }
+            _ => (),
+        }
+    }
+    match (x, y) {
+        (Some(x), Some(y)) => parsed.size = Some([y, x]),
+        _ => return Err(err(String::from("both `x` and `y` are required"))),
+    }
+    Ok(parsed)
+}
+
+/// Formats where each line of the file is a line of the pattern
+/// (except comment lines).
+/// Trailing whitespace is ignored.
+fn parse_grid<F>(data: &str, comment: Option<char>, cell: F) -> Result<Rows, ParseError>
+where
+    F: Fn(char) -> Option<Instr>,
+{
+    let mut rows = Vec::new();
+    for (n, line) in data.lines().enumerate() {
+        if matches!(comment, Some(c) if line.starts_with(c)) {
+            continue;
+        }
+        let mut row = Vec::new();
+        for (k, c) in line.trim_end().chars().enumerate() {
+            match cell(c) {
+                Some(instr) => row.push(instr),
+                None => {
+                    return Err(ParseError::Unexpected {
+                        line: n + 1,
+                        col: k + 1,
+                        c,
+                    })
+                }
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Longest run of cells or lines accepted in a RLE file without a header to
+/// bound the size of the pattern
+const MAX_RUN: usize = 1 << 16;
+
+/// Run Length Encoded format.
+///
+/// `b` and `.` are dead cells, `o` and any other state (`A`-`X`, optionally
+/// prefixed by `p`-`y` for multistate rules) are alive.
+/// The `x = {}, y = {}, rule = {}` header gives the size and rule, the
+/// pattern must fit in it.
+fn parse_rle(data: &str) -> Result<Parsed, ParseError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut header = None;
+    // a run count (and a multistate prefix) may be split from its state by
+    // a newline, so they are kept from one line to the next
+    let mut cnt = 0;
+    let mut counting = None;
+    let mut prefix = None;
+    for (n, line) in data.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('#') {
+            continue;
+        }
+        if header.is_none() && trimmed.starts_with('x') {
+            header = Some(parse_rle_header(trimmed, n)?);
+            continue;
+        }
+        let [hgt, wth] = header
+            .as_ref()
+            .and_then(|h| h.size)
+            .unwrap_or([MAX_RUN, MAX_RUN]);
+        let invalid = |msg: String| ParseError::Invalid { line: n + 1, msg };
+        for (k, c) in line.chars().enumerate() {
+            let pos = (n + 1, k + 1);
+            let run = if cnt == 0 { 1 } else { cnt };
+            let len = row.len().saturating_add(run);
+            if matches!(c, 'o' | 'b' | '.' | 'A'..='X') && (rows.len() >= hgt || len > wth)
+                || c == '$' && rows.len().saturating_add(run) > hgt
+            {
+                return Err(invalid(format!(
+                    "pattern larger than x = {}, y = {}",
+                    wth, hgt
+                )));
+            }
+            match (c, prefix) {
+                (d @ '0'..='9', None) => {
+                    cnt = cnt
+                        .checked_mul(10)
+                        .and_then(|cnt| cnt.checked_add(d.to_digit(10).unwrap() as usize))
+                        .ok_or_else(|| invalid(String::from("run count too large")))?;
+                    counting = Some(pos);
+                    continue;
+                }
+                ('p'..='y', None) => {
+                    prefix = Some((pos, c));
+                    continue;
+                }
+                ('A'..='X', _) => row.resize(len, Some(true)),
+                // a prefix must be followed by a state
+                (_, Some(((line, col), c))) => {
+                    return Err(ParseError::Unexpected { line, col, c });
+                }
+                ('o', None) => row.resize(len, Some(true)),
+                ('b', None) | ('.', None) => row.resize(len, Some(false)),
+                ('$', None) => {
+                    rows.push(row);
+                    for _ in 1..run {
+                        rows.push(Vec::new());
+                    }
+                    row = Vec::new();
+                }
+                ('!', None) => {
+                    if cnt > 0 {
+                        return Err(ParseError::DanglingCount {
+                            line: pos.0,
+                            col: pos.1,
+                        });
+                    }
+                    rows.push(row);
+                    return Ok(with_rows(header, rows));
+                }
+                (' ', None) | ('\t', None) if cnt == 0 => continue,
+                (c, None) => {
+                    return Err(ParseError::Unexpected {
+                        line: pos.0,
+                        col: pos.1,
+                        c,
+                    })
+                }
+            }
+            cnt = 0;
+            counting = None;
+            prefix = None;
+        }
+    }
+    if let Some(((line, col), c)) = prefix {
+        return Err(ParseError::Unexpected { line, col, c });
+    }
+    if let Some((line, col)) = counting {
+        return Err(ParseError::DanglingCount { line, col });
+    }
+    rows.push(row);
+    Ok(with_rows(header, rows))
+}
+
+/// Attach the body of a RLE file to its header (if it had one)
+fn with_rows(header: Option<Parsed>, rows: Rows) -> Parsed {
+    match header {
+        Some(mut h) => {
+            h.rows = rows;
+            h
+        }
+        None => Parsed::new(rows),
+    }
+}
+
+/// Build rows from a list of live cells, the top-left corner of their
+/// bounding box becomes the origin.
+/// Cells that are not listed are left unchanged when the pattern is added.
+fn from_cells(cells: &[[isize; 2]]) -> Rows {
+    let imin = cells.iter().map(|c| c[0]).min().unwrap_or(0);
+    let jmin = cells.iter().map(|c| c[1]).min().unwrap_or(0);
+    let hgt = cells.iter().map(|c| c[0] - imin + 1).max().unwrap_or(0) as usize;
+    let mut rows: Rows = vec![Vec::new(); hgt];
+    for [i, j] in cells {
+        let row = &mut rows[(i - imin) as usize];
+        let j = (j - jmin) as usize;
+        if row.len() <= j {
+            row.resize(j + 1, None);
+        }
+        row[j] = Some(true);
+    }
+    rows
+}
+
+/// Life 1.06: one `x y` line per live cell, `x` being the column
+fn parse_life106(data: &str) -> Result<Parsed, ParseError> {
+    let mut cells = Vec::new();
+    for (n, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let coords: Vec<_> = line.split_whitespace().map(str::parse::<isize>).collect();
+        match coords[..] {
+            [Ok(x), Ok(y)] => cells.push([y, x]),
+            _ => {
+                return Err(ParseError::Invalid {
+                    line: n + 1,
+                    msg: format!("`{}` is not a pair of coordinates", line),
+                })
+            }
+        }
+    }
+    Ok(Parsed::new(from_cells(&cells)))
+}
+
+/// A node of the quadtree of a macrocell file
+enum Node {
+    /// 8x8 square, given by the coordinates of its live cells
+    Leaf(Vec<[isize; 2]>),
+    /// 2x2 square of states (only in multistate files)
+    Unit([usize; 4]),
+    /// Square of side `2^level` divided in 4 quadrants (`nw`, `ne`, `sw`, `se`)
+    Split(u32, [usize; 4]),
+}
+
+/// Macrocell (Golly's `.mc`): a quadtree where each line defines a node,
+/// either an 8x8 leaf written as `.`, `*` and `$` or a node of level `k`
+/// written as `k nw ne sw se` where the children are indexes of previous
+/// lines (0 is an empty square).
+/// The last node is the root.
+fn parse_macrocell(data: &str) -> Result<Parsed, ParseError> {
+    let mut lines = data.lines().enumerate();
+    match lines.next() {
+        Some((_, l)) if l.starts_with("[M2]") => (),
+        _ => {
+            return Err(ParseError::Header {
+                line: 1,
+                msg: String::from("macrocell files start with `[M2]`"),
+            })
+        }
+    }
+    let mut rule = None;
+    // node 0 is the empty square
+    let mut nodes = vec![Node::Leaf(Vec::new())];
+    for (n, line) in lines {
+        let line = line.trim_end();
+        if let Some(r) = line.strip_prefix("#R") {
+            rule = Some(String::from(r.trim()));
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let invalid = |msg: String| ParseError::Invalid { line: n + 1, msg };
+        if line.starts_with(|c: char| c.is_ascii_digit()) {
+            let v: Vec<_> = line.split_whitespace().map(str::parse::<usize>).collect();
+            let v = match v[..] {
+                [Ok(k), Ok(a), Ok(b), Ok(c), Ok(d)] => [k, a, b, c, d],
+                _ => {
+                    return Err(invalid(format!(
+                        "`{}` is not of the form `k nw ne sw se`",
+                        line
+                    )))
+                }
+            };
+            let children = [v[1], v[2], v[3], v[4]];
+            if v[0] == 1 {
+                nodes.push(Node::Unit(children));
+                continue;
+            }
+            if v[0] == 0 {
+                return Err(invalid(String::from("nodes start at level 1")));
+            }
+            if v[0] > 62 {
+                return Err(invalid(format!("level {} is too deep", v[0])));
+            }
+            if let Some(&c) = children.iter().find(|&&c| c >= nodes.len()) {
+                return Err(invalid(format!("node {} is not yet defined", c)));
+            }
+            nodes.push(Node::Split(v[0] as u32, children));
+        } else {
+            let mut cells = Vec::new();
+            let (mut i, mut j) = (0, 0);
+            for (k, c) in line.chars().enumerate() {
+                // cells must be inside the leaf, only the line after its last
+                // one can be started
+                let outside = match c {
+                    '.' | '*' => i >= 8 || j >= 8,
+                    '$' => i >= 8,
+                    _ => false,
+                };
+                if outside {
+                    return Err(invalid(String::from("leaf does not fit in 8x8")));
+                }
+                match c {
+                    '.' => j += 1,
+                    '*' => {
+                        cells.push([i, j]);
+                        j += 1;
+                    }
+                    '$' => {
+                        i += 1;
+                        j = 0;
+                    }
+                    c => {
+                        return Err(ParseError::Unexpected {
+                            line: n + 1,
+                            col: k + 1,
+                            c,
+                        })
+                    }
+                }
+            }
+            nodes.push(Node::Leaf(cells));
+        }
+    }
+    let mut cells = Vec::new();
+    expand(&nodes, nodes.len() - 1, [0, 0], &mut cells);
+    let parsed = Parsed::new(from_cells(&cells));
+    Ok(match rule {
+        Some(r) => parsed.with_rule(&r),
+        None => parsed,
+    })
+}
+
+/// Collect the live cells of a macrocell node whose top-left corner is at
+/// `[i0, j0]`
+fn expand(nodes: &[Node], idx: usize, [i0, j0]: [isize; 2], cells: &mut Vec<[isize; 2]>) {
+    if idx == 0 {
+        return;
+    }
+    match &nodes[idx] {
+        Node::Leaf(v) => cells.extend(v.iter().map(|[i, j]| [i0 + i, j0 + j])),
+        Node::Unit(states) => {
+            for (k, &s) in states.iter().enumerate() {
+                if s != 0 {
+                    cells.push([i0 + (k / 2) as isize, j0 + (k % 2) as isize]);
+                }
+            }
+        }
+        Node::Split(level, children) => {
+            let half = 1 << (level - 1);
+            let corners = [[0, 0], [0, half], [half, 0], [half, half]];
+            for (&c, [di, dj]) in children.iter().zip(corners.iter()) {
+                expand(nodes, c, [i0 + di, j0 + dj], cells);
+            }
+        }
+    }
+}
+
+/// Value of a single digit of the extended Wechsler format (`0`-`9`, `a`-`z`)
+fn wechsler_digit(c: char) -> Option<usize> {
+    c.to_digit(36).map(|d| d as usize)
+}
+
+/// Decode an object identifier as produced by apgsearch (and `census`), e.g.
+/// `xs4_33` for a block or `xq4_153` for a glider.
+/// Only the part after the first `_` describes the cells: strips of 5 lines
+/// separated by `z`, where each character is a column of the strip and `w`,
+/// `x`, `y?` encode runs of empty columns.
+pub fn parse_apgcode(code: &str) -> Result<Parsed, ParseError> {
+    let (prefix, body) = match code.find('_') {
+        Some(k) => (&code[..k], &code[k + 1..]),
+        None => {
+            return Err(ParseError::Header {
+                line: 1,
+                msg: format!("`{}` has no prefix", code),
+            })
+        }
+    };
+    if !(prefix.starts_with("xs") || prefix.starts_with("xp") || prefix.starts_with("xq")) {
+        return Err(ParseError::Header {
+            line: 1,
+            msg: format!(
+                "`{}` does not describe a still life, oscillator or spaceship",
+                prefix
+            ),
+        });
+    }
+    let mut cells = Vec::new();
+    let (mut strip, mut j) = (0, 0);
+    let mut chars = body.chars().enumerate();
+    while let Some((k, c)) = chars.next() {
+        let col = prefix.len() + 2 + k;
+        match c {
+            'w' => j += 2,
+            'x' => j += 3,
+            'y' => match chars.next().and_then(|(_, c)| wechsler_digit(c)) {
+                Some(n) => j += 4 + n as isize,
+                None => {
+                    return Err(ParseError::DanglingCount { line: 1, col });
+                }
+            },
+            'z' => {
+                strip += 1;
+                j = 0;
+            }
+            c => match wechsler_digit(c) {
+                Some(v) if v < 32 => {
+                    for bit in 0..5 {
+                        if v & (1 << bit) != 0 {
+                            cells.push([5 * strip + bit, j]);
+                        }
+                    }
+                    j += 1;
+                }
+                _ => return Err(ParseError::Unexpected { line: 1, col, c }),
+            },
+        }
+    }
+    Ok(Parsed::new(from_cells(&cells)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    fn parse(name: &str) -> Result<Parsed, ParseError> {
+        parse_file(&format!("data/malformed/{}", name))
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        let cases = [
+            ("bad_char.cells", 3, 2, '#'),
+            ("bad_char.lif", 4, 2, 'o'),
+            // `q` is a multistate prefix that is not followed by a state
+            ("bad_char.rle", 2, 9, 'q'),
+        ];
+        for &(name, l, k, ch) in &cases {
+            match parse(name) {
+                Err(ParseError::Unexpected { line, col, c }) => {
+                    assert_eq!((line, col, c), (l, k, ch), "{}", name)
+                }
+                other => panic!("{}: {:?}", name, other.map(|p| p.rows)),
+            }
+        }
+    }
+
+    #[test]
+    fn dangling_count() {
+        match parse("count_before_end.rle") {
+            Err(ParseError::DanglingCount { line, col }) => assert_eq!((line, col), (2, 11)),
+            other => panic!("{:?}", other.map(|p| p.rows)),
+        }
+    }
+
+    #[test]
+    fn unknown_format() {
+        match parse("unknown_format") {
+            Err(ParseError::UnknownFormat(file)) => assert!(file.ends_with("unknown_format")),
+            other => panic!("{:?}", other.map(|p| p.rows)),
+        }
+    }
+
+    #[test]
+    fn macrocell_levels() {
+        let glider = "[M2]\n.*$..*$***$\n4 1 0 0 0\n";
+        let rows = parse_macrocell(glider).unwrap().rows;
+        assert_eq!(Pattern::from_rows(&rows).len(), 5);
+        // the last line and column of a leaf, with a trailing `$`
+        let corner = "[M2]\n$$$$$$$.......*$\n4 1 0 0 0\n";
+        let rows = parse_macrocell(corner).unwrap().rows;
+        assert_eq!(rows, vec![vec![Some(true)]]);
+        let cases = [
+            ("0 0 0 0 0", 2),
+            (".*$\n63 1 1 1 1", 3),
+            // a ninth line or column
+            ("$$$$$$$$*\n4 1 0 0 0", 2),
+            ("........*\n4 1 0 0 0", 2),
+            ("*$$$$$$$$$\n4 1 0 0 0", 2),
+        ];
+        for (body, line) in &cases {
+            match parse_macrocell(&format!("[M2]\n{}\n", body)) {
+                Err(ParseError::Invalid { line: l, .. }) => assert_eq!(l, *line),
+                other => panic!("{:?}", other.map(|p| p.rows)),
+            }
+        }
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(parse_rule("B3/S23").as_deref(), Some("3-23"));
+        assert_eq!(parse_rule("s23/b36").as_deref(), Some("36-23"));
+        assert_eq!(parse_rule("23/3").as_deref(), Some("3-23"));
+        assert_eq!(parse_rule(" b2/s ").as_deref(), Some("2-"));
+        assert_eq!(parse_rule("B3/S23:T10,10").as_deref(), Some("3-23"));
+        assert_eq!(parse_rule("b36/s23:P20,20").as_deref(), Some("36-23"));
+        assert_eq!(parse_rule("B9/S23"), None);
+        assert_eq!(parse_rule("Generations"), None);
+    }
+
+    #[test]
+    fn rle_header() {
+        let parsed = parse_str(
+            "#N Blinker\nx = 10, y = 8, rule = B36/S23\n3o!\n",
+            Format::Rle,
+        )
+        .unwrap();
+        assert_eq!(parsed.dims(), [8, 10]);
+        assert_eq!(parsed.rule_name.as_deref(), Some("B36/S23"));
+        assert_eq!(parsed.rule.as_deref(), Some("36-23"));
+        assert_eq!(parsed.name.as_deref(), Some("Blinker"));
+        let parsed = parse_str("x = 3, y = 1\n3o!\n", Format::Rle).unwrap();
+        assert_eq!(parsed.rule, None);
+        for rule in &["B3/S23:T10,10", "B3/S23:P20,20", "B3/S23:T10,10 "] {
+            let data = format!("x = 3, y = 1, rule = {}\n3o!\n", rule);
+            let parsed = parse_str(&data, Format::Rle).unwrap();
+            assert_eq!(parsed.rule_name.as_deref(), Some(rule.trim()));
+            assert_eq!(parsed.rule.as_deref(), Some("3-23"));
+        }
+        for (header, line) in &[("x = 3\n3o!", 1), ("#C\nx = a, y = 1\n3o!", 2)] {
+            match parse_str(header, Format::Rle) {
+                Err(ParseError::Header { line: l, .. }) => assert_eq!(l, *line),
+                other => panic!("{:?}", other.map(|p| p.rows)),
+            }
+        }
+    }
+
+    #[test]
+    fn rle_runs() {
+        let cases = [
+            ("99999999999999999999999o!", 1),
+            ("99999999999o!", 1),
+            ("18446744073709551615o!", 1),
+            ("o99999999999$o!", 1),
+            ("x = 3, y = 2\n4o!", 2),
+            ("x = 3, y = 2\n3o$\n3o$o!", 3),
+            ("x = 3, y = 2\no2$o!", 2),
+        ];
+        for &(data, line) in &cases {
+            match parse_str(data, Format::Rle) {
+                Err(ParseError::Invalid { line: l, .. }) => assert_eq!(l, line, "{}", data),
+                other => panic!("{}: {:?}", data, other.map(|p| p.rows)),
+            }
+        }
+        // a trailing newline can go one line past the pattern
+        let (o, b) = (Some(true), Some(false));
+        let rows = parse_str("x = 3, y = 2\n3o$b2o$!", Format::Rle)
+            .unwrap()
+            .rows;
+        assert_eq!(rows, vec![vec![o, o, o], vec![b, o, o], vec![]]);
+    }
+
+    #[test]
+    fn well_formed() {
+        let (o, b) = (Some(true), Some(false));
+        let glider = vec![vec![b, o], vec![b, b, o], vec![o, o, o]];
+        assert_eq!(parse("glider_ok.rle").unwrap().rows, glider);
+        // a run count may be separated from its state by a newline
+        assert_eq!(parse("split_count.rle").unwrap().rows, glider);
+        assert_eq!(
+            parse("multistate_ok.rle").unwrap().rows,
+            vec![vec![b, o, b], vec![o, o, b, b]]
+        );
+        assert_eq!(
+            parse("no_extension_ok").unwrap().rows,
+            vec![vec![o, o, o], vec![b, o, b]]
+        );
+        assert_eq!(
+            parse("trailing_space_ok.cells").unwrap().rows,
+            vec![vec![o, o, o]]
+        );
+        assert!(parse("empty_ok.txt").unwrap().rows.is_empty());
+    }
+}
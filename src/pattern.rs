@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+use std::ops::{BitAnd, BitOr, BitXor};
+
+use crate::lifelike::*;
+use crate::parse::*;
+
+/// A set of live cells, independent of any field.
+///
+/// Coordinates are `[i, j]` (line then column) and may be negative,
+/// patterns are only placed on a field when they are stamped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pattern {
+    cells: BTreeSet<[isize; 2]>,
+}
+
+impl Pattern {
+    /// Empty pattern
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pattern made of the given live cells
+    pub fn from_cells<I: IntoIterator<Item = [isize; 2]>>(cells: I) -> Self {
+        Self {
+            cells: cells.into_iter().collect(),
+        }
+    }
+
+    /// Live cells of a parsed file (dead and blank cells are dropped),
+    /// the first line and column of the file are at `[0, 0]`
+    pub fn from_rows(rows: &[Vec<Instr>]) -> Self {
+        Self::from_cells(rows.iter().enumerate().flat_map(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .filter(|(_, c)| **c == Some(true))
+                .map(move |(j, _)| [i as isize, j as isize])
+        }))
+    }
+
+    /// Read a pattern from a file (any format supported by `parse_file`)
+    pub fn from_file(file: &str) -> Result<Self, ParseError> {
+        Ok(Self::from_rows(&parse_file(file)?.rows))
+    }
+
+    /// Decode an apgcode such as `xq4_153`
+    pub fn from_apgcode(code: &str) -> Result<Self, ParseError> {
+        Ok(Self::from_rows(&parse_apgcode(code)?.rows))
+    }
+
+    /// Copy all live cells of a game
+    pub fn from_game(game: &LifeLike) -> Self {
+        Self::from_cells(
+            game.live_cells()
+                .into_iter()
+                .map(|[i, j]| [i as isize, j as isize]),
+        )
+    }
+
+    /// Live cells, in reading order
+    pub fn cells(&self) -> impl Iterator<Item = &[isize; 2]> {
+        self.cells.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn contains(&self, i: isize, j: isize) -> bool {
+        self.cells.contains(&[i, j])
+    }
+
+    /// Smallest rectangle `([i0, j0], [hgt, wth])` that contains all cells
+    pub fn bounding_box(&self) -> Option<([isize; 2], [usize; 2])> {
+        let imin = self.cells.iter().map(|c| c[0]).min()?;
+        let imax = self.cells.iter().map(|c| c[0]).max()?;
+        let jmin = self.cells.iter().map(|c| c[1]).min()?;
+        let jmax = self.cells.iter().map(|c| c[1]).max()?;
+        Some((
+            [imin, jmin],
+            [(imax - imin + 1) as usize, (jmax - jmin + 1) as usize],
+        ))
+    }
+
+    /// Move all cells by `[di, dj]`
+    pub fn translate(&self, di: isize, dj: isize) -> Self {
+        Self::from_cells(self.cells.iter().map(|[i, j]| [i + di, j + dj]))
+    }
+
+    /// Move the pattern so that the top-left corner of its bounding box is
+    /// at `[0, 0]`
+    pub fn normalize(&self) -> Self {
+        match self.bounding_box() {
+            Some(([i0, j0], _)) => self.translate(-i0, -j0),
+            None => self.clone(),
+        }
+    }
+
+    /// Apply a rotation/symmetry, the top-left corner of the bounding box
+    /// does not move
+    pub fn transform(&self, t: &Transform) -> Self {
+        self.transform_at(t, Anchor::TopLeft)
+    }
+
+    /// Apply a rotation/symmetry, keeping the anchor in place (relative to
+    /// the bounding box, see `Transform::place`)
+    pub fn transform_at(&self, t: &Transform, anchor: Anchor) -> Self {
+        let ([i0, j0], size) = match self.bounding_box() {
+            Some(b) => b,
+            None => return self.clone(),
+        };
+        // where the anchor is relative to the top-left corner, before the
+        // transformation
+        let [ci, cj] = T_NONE.place([0, 0], size, anchor);
+        Self::from_cells(self.cells.iter().map(|[i, j]| {
+            let [ti, tj] = t.place([i - i0, j - j0], size, anchor);
+            [i0 + ti - ci, j0 + tj - cj]
+        }))
+    }
+
+    /// Quarter turn counterclockwise
+    pub fn rotate_left(&self) -> Self {
+        self.transform(&T_LT)
+    }
+
+    /// Quarter turn clockwise
+    pub fn rotate_right(&self) -> Self {
+        self.transform(&T_RT)
+    }
+
+    /// Left-right symmetry
+    pub fn mirror(&self) -> Self {
+        self.transform(&T_NONE_SYM)
+    }
+
+    /// Cells that are alive in either pattern
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            cells: self.cells.union(&other.cells).copied().collect(),
+        }
+    }
+
+    /// Cells that are alive in both patterns
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self {
+            cells: self.cells.intersection(&other.cells).copied().collect(),
+        }
+    }
+
+    /// Cells that are alive in exactly one of the patterns
+    pub fn xor(&self, other: &Self) -> Self {
+        Self {
+            cells: self
+                .cells
+                .symmetric_difference(&other.cells)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Birth the cells of the pattern in a game, after applying `t`, so
+    /// that the anchor of the bounding box is at `[i0, j0]` (with wrapping).
+    /// Cells of the game that are not in the pattern are left unchanged.
+    pub fn stamp(&self, game: &mut LifeLike, i0: isize, j0: isize, t: &Transform, anchor: Anchor) {
+        let ([bi, bj], size) = match self.bounding_box() {
+            Some(b) => b,
+            None => return,
+        };
+        let cells: Vec<_> = self
+            .cells
+            .iter()
+            .map(|[i, j]| t.place([i - bi, j - bj], size, anchor))
+            .collect();
+        game.add_cells(&cells, i0, j0);
+    }
+}
+
+impl BitOr for &Pattern {
+    type Output = Pattern;
+
+    fn bitor(self, other: &Pattern) -> Pattern {
+        self.union(other)
+    }
+}
+
+impl BitAnd for &Pattern {
+    type Output = Pattern;
+
+    fn bitand(self, other: &Pattern) -> Pattern {
+        self.intersection(other)
+    }
+}
+
+impl BitXor for &Pattern {
+    type Output = Pattern;
+
+    fn bitxor(self, other: &Pattern) -> Pattern {
+        self.xor(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glider() -> Pattern {
+        Pattern::from_apgcode("xq4_153").unwrap()
+    }
+
+    #[test]
+    fn apgcode() {
+        assert_eq!(
+            glider(),
+            Pattern::from_cells(vec![[0, 0], [0, 1], [0, 2], [1, 2], [2, 1]])
+        );
+        assert_eq!(Pattern::from_apgcode("xs4_33").unwrap().len(), 4);
+        assert!(Pattern::from_apgcode("xs4_3?").is_err());
+    }
+
+    #[test]
+    fn boxes() {
+        let pat = glider().translate(-3, 5);
+        assert_eq!(pat.bounding_box(), Some(([-3, 5], [3, 3])));
+        assert_eq!(pat.normalize(), glider());
+        assert!(pat.contains(-3, 7));
+        assert_eq!(Pattern::new().bounding_box(), None);
+        assert_eq!(Pattern::new().normalize(), Pattern::new());
+    }
+
+    #[test]
+    fn rotations() {
+        let pat = Pattern::from_cells(vec![[0, 0], [0, 1], [0, 2], [1, 0]]).translate(4, -2);
+        assert_eq!(pat.rotate_left().rotate_right(), pat);
+        assert_eq!(pat.mirror().mirror(), pat);
+        let four = (0..4).fold(pat.clone(), |p, _| p.rotate_right());
+        assert_eq!(four, pat);
+        // the top-left corner stays in place
+        assert_eq!(pat.rotate_right().bounding_box(), Some(([4, -2], [3, 2])));
+        assert_eq!(
+            pat.rotate_right().normalize(),
+            Pattern::from_cells(vec![[0, 0], [0, 1], [1, 1], [2, 1]])
+        );
+        // so does the center of a square box
+        let square = Pattern::from_cells(vec![[0, 0], [2, 2], [0, 1]]);
+        assert_eq!(
+            square.transform_at(&T_DB, Anchor::Center),
+            Pattern::from_cells(vec![[2, 2], [0, 0], [2, 1]])
+        );
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = Pattern::from_cells(vec![[0, 0], [0, 1], [1, 1]]);
+        let b = Pattern::from_cells(vec![[1, 1], [2, 2]]);
+        assert_eq!(
+            &a | &b,
+            Pattern::from_cells(vec![[0, 0], [0, 1], [1, 1], [2, 2]])
+        );
+        assert_eq!(&a & &b, Pattern::from_cells(vec![[1, 1]]));
+        assert_eq!(&a ^ &b, Pattern::from_cells(vec![[0, 0], [0, 1], [2, 2]]));
+    }
+
+    #[test]
+    fn stamp() {
+        let mut game = LifeLike::new(10, 10, LIFE);
+        // wraps around the bottom-right corner
+        glider().stamp(&mut game, 8, 8, &T_NONE, Anchor::TopLeft);
+        assert_eq!(
+            Pattern::from_game(&game),
+            Pattern::from_cells(vec![[8, 8], [8, 9], [8, 0], [9, 0], [0, 9]])
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::lifelike::*;
+use crate::parse::ParseError;
+
+/// Long-term behavior of a life-like pattern
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Behavior {
+    /// All cells died
+    Dead,
+    /// Period 1, no displacement
+    StillLife,
+    /// Comes back to the same state in the same place after `period`
+    /// generations
+    Oscillator(usize),
+    /// Comes back to the same state after `period` generations,
+    /// shifted by `dx` columns (positive = right) and `dy` lines
+    /// (positive = down)
+    Spaceship { dx: isize, dy: isize, period: usize },
+}
+
+/// Outcome of running a pattern until it repeats itself
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    pub behavior: Behavior,
+    /// First generation of the cycle, i.e. the generation at which a soup
+    /// has stabilized
+    pub stable_at: usize,
+    /// Population at the time the cycle was detected
+    pub population: usize,
+}
+
+impl fmt::Display for Behavior {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Behavior::Dead => write!(f, "dies out"),
+            Behavior::StillLife => write!(f, "still life"),
+            Behavior::Oscillator(p) => write!(f, "oscillator of period {}", p),
+            Behavior::Spaceship { dx, dy, period } => {
+                write!(f, "spaceship ({}, {})/{}", dx, dy, period)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (stable from generation {}, population {})",
+            self.behavior, self.stable_at, self.population
+        )
+    }
+}
+
+/// Translation-invariant fingerprint of a set of live cells.
+/// Returns the hash, the normalized cells (to tell apart states whose
+/// hashes collide) and the top-left corner of the bounding box that was
+/// used to normalize the coordinates.
+///
+/// Note that the field is a torus: a spaceship that crosses an edge will
+/// see its bounding box jump, which delays detection until it has moved
+/// away from the edge.
+fn fingerprint(cells: &[[usize; 2]]) -> (u64, Vec<[usize; 2]>, [isize; 2]) {
+    let imin = cells.iter().map(|c| c[0]).min().unwrap_or(0);
+    let jmin = cells.iter().map(|c| c[1]).min().unwrap_or(0);
+    let norm: Vec<_> = cells.iter().map(|[i, j]| [i - imin, j - jmin]).collect();
+    let mut h = DefaultHasher::new();
+    norm.hash(&mut h);
+    (h.finish(), norm, [imin as isize, jmin as isize])
+}
+
+/// A state that was seen: generation, top-left corner and normalized cells
+type Seen = (usize, [isize; 2], Vec<[usize; 2]>);
+
+/// Remembers every state seen so far to detect when one of them repeats
+pub struct Detector {
+    seen: HashMap<u64, Vec<Seen>>,
+    gen: usize,
+}
+
+impl Detector {
+    pub fn new() -> Self {
+        Self {
+            seen: HashMap::new(),
+            gen: 0,
+        }
+    }
+
+    /// Register the current state of the game.
+    /// Returns the behavior as soon as a previously seen state is found again.
+    pub fn observe(&mut self, game: &LifeLike) -> Option<Report> {
+        let cells = game.live_cells();
+        let gen = self.gen;
+        self.gen += 1;
+        if cells.is_empty() {
+            return Some(Report {
+                behavior: Behavior::Dead,
+                stable_at: gen,
+                population: 0,
+            });
+        }
+        let (h, norm, [i, j]) = fingerprint(&cells);
+        // a hash match alone is not enough, the cells must be the same
+        let same = self
+            .seen
+            .get(&h)
+            .and_then(|v| v.iter().find(|(_, _, c)| *c == norm));
+        match same {
+            None => {
+                self.seen.entry(h).or_default().push((gen, [i, j], norm));
+                None
+            }
+            Some(&(g0, [i0, j0], _)) => {
+                let period = gen - g0;
+                let (dx, dy) = (j - j0, i - i0);
+                let behavior = if dx != 0 || dy != 0 {
+                    Behavior::Spaceship { dx, dy, period }
+                } else if period == 1 {
+                    Behavior::StillLife
+                } else {
+                    Behavior::Oscillator(period)
+                };
+                Some(Report {
+                    behavior,
+                    stable_at: g0,
+                    population: cells.len(),
+                })
+            }
+        }
+    }
+}
+
+/// Run the game until its state repeats (up to translation), for at most
+/// `max_gen` generations.
+/// The game is left in the state where the repetition was detected.
+pub fn classify(game: &mut LifeLike, max_gen: usize) -> Option<Report> {
+    let mut det = Detector::new();
+    for _ in 0..=max_gen {
+        if let Some(r) = det.observe(game) {
+            return Some(r);
+        }
+        game.next();
+    }
+    None
+}
+
+/// Load a pattern alone on a field large enough to let it evolve, and
+/// report its period and displacement (if one is found within `max_gen`
+/// generations).
+pub fn classify_file(
+    file: &str,
+    hgt: usize,
+    wth: usize,
+    rules: &str,
+    max_gen: usize,
+) -> Result<Option<Report>, ParseError> {
+    let mut game = LifeLike::new(hgt, wth, rules);
+    game.add_from_file(file, (hgt / 3) as isize, (wth / 3) as isize, T_NONE)?;
+    Ok(classify(&mut game, max_gen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    fn run(code: &str) -> Report {
+        let mut game = LifeLike::new(40, 40, LIFE);
+        Pattern::from_apgcode(code)
+            .unwrap()
+            .stamp(&mut game, 10, 10, &T_NONE, Anchor::TopLeft);
+        classify(&mut game, 100).unwrap()
+    }
+
+    #[test]
+    fn behaviors() {
+        assert_eq!(run("xs4_33").behavior, Behavior::StillLife);
+        assert_eq!(run("xp2_7").behavior, Behavior::Oscillator(2));
+        assert_eq!(
+            run("xq4_153").behavior,
+            Behavior::Spaceship {
+                dx: 1,
+                dy: -1,
+                period: 4
+            }
+        );
+        let r = run("xs2_11");
+        assert_eq!((r.behavior, r.stable_at), (Behavior::Dead, 1));
+    }
+
+    #[test]
+    fn collisions() {
+        // same hash but different cells: not a repetition
+        let mut det = Detector::new();
+        det.gen = 1;
+        let mut game = LifeLike::new(10, 10, LIFE);
+        Pattern::from_apgcode("xs4_33")
+            .unwrap()
+            .stamp(&mut game, 2, 2, &T_NONE, Anchor::TopLeft);
+        let cells = game.live_cells();
+        let (h, norm, corner) = fingerprint(&cells);
+        det.seen.insert(h, vec![(0, corner, vec![[1, 1]])]);
+        assert!(det.observe(&game).is_none());
+        assert_eq!(det.seen[&h].len(), 2);
+        assert_eq!(det.seen[&h][1].2, norm);
+        let r = det.observe(&game).unwrap();
+        assert_eq!((r.behavior, r.stable_at), (Behavior::StillLife, 1));
+    }
+}
@@ -1,10 +1,181 @@
 use crate::canvas::*;
+use crate::parse::ParseError;
+use crate::source::{generator, Source, Sources};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::cmp::Ordering;
 use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::{Add, Sub};
+
+/// Highest pile that does not topple
+pub const MAX_STABLE: usize = 3;
+
+/// Which piles receive grains when a pile topples
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// The 4 direct neighbors
+    VonNeumann,
+    /// The 8 neighbors, including diagonals
+    Moore,
+    /// The 6 neighbors of a hexagonal grid drawn on a square one: the 4
+    /// direct neighbors and the diagonal `[-1, -1]`, `[1, 1]`
+    Hex,
+}
+
+impl Neighborhood {
+    /// Offsets `(di, dj)` of the neighbors
+    pub fn moves(self) -> &'static [(isize, isize)] {
+        match self {
+            Neighborhood::VonNeumann => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+            Neighborhood::Moore => &[
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ],
+            Neighborhood::Hex => &[(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, -1)],
+        }
+    }
+
+    /// Number of neighbors, which is also the usual threshold
+    pub fn size(self) -> usize {
+        self.moves().len()
+    }
+
+    /// Number of neighbors that receive grains with `model`
+    fn receivers(self, model: Model) -> usize {
+        self.moves()
+            .iter()
+            .filter(|(di, _)| model != Model::Directed || *di > 0)
+            .count()
+    }
+}
+
+/// Names of the neighborhoods in the text format of `Sandpile::save`, their
+/// index is used in the binary format
+const NEIGHBORHOODS: [(Neighborhood, &str); 3] = [
+    (Neighborhood::VonNeumann, "vonneumann"),
+    (Neighborhood::Moore, "moore"),
+    (Neighborhood::Hex, "hex"),
+];
+
+/// What happens to the grains that fall off the border
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// They are lost: the usual abelian sandpile
+    Sink,
+    /// There is no border, the field wraps around. Grains that reach the
+    /// pile `[i, j]` are lost instead.
+    Torus([usize; 2]),
+    /// They stay on the pile that toppled, so that no grain is ever lost
+    Closed,
+}
+
+/// How the grains of a toppling pile are distributed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// Bak-Tang-Wiesenfeld: one grain to each neighbor
+    Btw,
+    /// Manna: each grain goes to a neighbor chosen at random.
+    /// The classic model uses a threshold of 2 (see `set_threshold`).
+    Manna,
+    /// Grains only flow downward: one grain to each neighbor of the line
+    /// below (2 with the hexagonal neighborhood, as in the model of Dhar and
+    /// Ramaswamy, 3 with the Moore neighborhood). Not available with the
+    /// von Neumann neighborhood, which has a single neighbor below.
+    Directed,
+}
+
+/// Names of the models, as for `NEIGHBORHOODS`
+const MODELS: [(Model, &str); 3] = [
+    (Model::Btw, "btw"),
+    (Model::Manna, "manna"),
+    (Model::Directed, "directed"),
+];
+
+/// Color of a pile of `hgt` grains that topples at `threshold`:
+/// from dark red to yellow, black for empty and unstable piles.
+/// Piles of 1, 2 and 3 grains out of 4 have exactly the original colors.
+pub fn shade(hgt: usize, threshold: usize) -> Color {
+    if hgt == 0 || hgt >= threshold {
+        return (0, 0, 0);
+    }
+    let shades = [(13., 4.), (25., 9.), (25., 20.)];
+    let t = if threshold > 2 {
+        2. * (hgt - 1) as f64 / (threshold - 2) as f64
+    } else {
+        2.
+    };
+    let k = (t as usize).min(1);
+    let x = t - k as f64;
+    let ((r0, g0), (r1, g1)) = (shades[k], shades[k + 1]);
+    ((r0 + (r1 - r0) * x) as u8, (g0 + (g1 - g0) * x) as u8, 0)
+}
+
+/// Color of a pile of `hgt` grains that topples at `threshold`: same as
+/// `shade` for stable piles, and for unstable ones from dark blue to white
+/// as the height grows (on a logarithmic scale, white above 256 times the
+/// threshold), so that heaps in the middle of an avalanche are visible.
+pub fn heat(hgt: usize, threshold: usize) -> Color {
+    if hgt < threshold {
+        return shade(hgt, threshold);
+    }
+    let t = ((hgt as f64 / threshold as f64).log2() / 8.).min(1.);
+    ((25. * t) as u8, (25. * t) as u8, (10. + 15. * t) as u8)
+}
+
+/// How the piles are drawn
+#[derive(Clone, Copy)]
+pub struct Palette {
+    /// Color of a pile from its height and threshold
+    pub map: fn(usize, usize) -> Color,
+    /// Color of the piles that toppled since the last frame, if they should
+    /// stand out
+    pub toppled: Option<Color>,
+}
+
+impl Palette {
+    /// Only stable piles are visible
+    pub const CLASSIC: Palette = Palette {
+        map: shade,
+        toppled: None,
+    };
+    /// Unstable piles are visible too
+    pub const HEAT: Palette = Palette {
+        map: heat,
+        toppled: None,
+    };
+    /// Unstable piles are visible, and piles that toppled are white
+    pub const AVALANCHE: Palette = Palette {
+        map: heat,
+        toppled: Some((25, 25, 25)),
+    };
+}
+
+/// How much of the stabilization is done between two frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// All piles that are unstable at the start of the step topple once
+    Wave,
+    /// At most this many piles topple, in the order they became unstable
+    /// (`Topples(0)` is the same as `Topples(1)`, so that each step makes
+    /// progress)
+    Topples(usize),
+}
 
 /// A single pile of grains in the sandpile
 #[derive(Clone, Copy)]
 struct Grain {
     hgt: usize,
+    /// Whether the pile toppled since the last frame, only used for colors
+    toppled: bool,
     /// scheduled indicates whether or not the pile is already planned for
     /// toppling in order to improve performance
     scheduled: bool,
@@ -15,23 +186,46 @@ impl Grain {
     pub fn new() -> Self {
         Grain {
             hgt: 0,
+            toppled: false,
             scheduled: false,
         }
     }
 }
 
+/// Default colors of the usual sandpile, the piles are drawn with the
+/// palette of the sandpile instead (see `Shade`)
 impl Colorize for Grain {
     fn color(&self) -> Color {
-        match self.hgt {
-            0 => (0, 0, 0),
-            1 => (13, 4, 0),
-            2 => (25, 9, 0),
-            3 => (25, 20, 0),
-            _ => (0, 0, 0),
-        }
+        shade(self.hgt, MAX_STABLE + 1)
+    }
+}
+
+/// Color of a pile, computed from the palette when rendering
+#[derive(Clone, Copy)]
+struct Shade(Color);
+
+impl Colorize for Shade {
+    fn color(&self) -> Color {
+        self.0
     }
 }
 
+/// What happened after a single grain was dropped
+#[derive(Clone, Copy, Debug)]
+pub struct Avalanche {
+    /// Where the grain was dropped
+    pub pos: [usize; 2],
+    /// Total number of topples
+    pub size: usize,
+    /// Number of distinct piles that toppled
+    pub area: usize,
+    /// Number of waves: in each wave the pile where the grain was dropped
+    /// topples once, then all others relax
+    pub waves: usize,
+    /// Largest distance between a toppled pile and `pos`
+    pub radius: f64,
+}
+
 /// A collection of grains
 pub struct Sandpile {
     field: Canvas<Grain>,
@@ -39,18 +233,490 @@ pub struct Sandpile {
     wth: usize,
     schedule: VecDeque<(usize, usize)>,
     cnt: usize,
+    threshold: usize,
+    neighborhood: Neighborhood,
+    boundary: Boundary,
+    model: Model,
+    /// Seed of `rng`, to save it
+    seed: u64,
+    rng: ChaCha8Rng,
+    sources: Sources,
+    palette: Palette,
 }
 
 impl Sandpile {
     /// Initialize an empty sandpile
     pub fn new(i: usize, j: usize) -> Self {
+        Self::with(i, j, Neighborhood::VonNeumann, Boundary::Sink)
+    }
+
+    /// Initialize an empty sandpile with other rules than the usual ones.
+    /// The threshold is the size of the neighborhood, see `set_threshold`
+    /// to change it.
+    pub fn with(i: usize, j: usize, neighborhood: Neighborhood, boundary: Boundary) -> Self {
+        let threshold = neighborhood.size();
         Sandpile {
             field: Canvas::new(i, j, Grain::new()),
             hgt: i,
             wth: j,
             schedule: VecDeque::new(),
             cnt: 0,
+            threshold,
+            neighborhood,
+            boundary,
+            model: Model::Btw,
+            seed: 0,
+            rng: generator(0, 0),
+            sources: Sources::new(0),
+            palette: Palette::CLASSIC,
+        }
+    }
+
+    /// Change the way grains are distributed, `seed` initializes the random
+    /// choices of the Manna model. The threshold is reset to the number of
+    /// neighbors that receive grains.
+    pub fn set_model(&mut self, model: Model, seed: u64) {
+        assert!(
+            model != Model::Directed || self.neighborhood != Neighborhood::VonNeumann,
+            "the directed model needs more than one neighbor below"
+        );
+        self.model = model;
+        self.seed = seed;
+        self.rng = generator(seed, 0);
+        let threshold = self.moves().count();
+        self.set_threshold(threshold);
+    }
+
+    /// Offsets of the neighbors that receive grains
+    fn moves(&self) -> impl Iterator<Item = &(isize, isize)> + '_ {
+        self.neighborhood
+            .moves()
+            .iter()
+            .filter(move |(di, _)| self.model != Model::Directed || *di > 0)
+    }
+
+    /// Piles topple when they have at least `threshold` grains, and give
+    /// one to each neighbor. The remaining `threshold - neighbors` grains
+    /// are lost.
+    ///
+    /// With the Manna model the threshold may also be lower than the number
+    /// of neighbors: the `threshold` grains then go to neighbors chosen at
+    /// random.
+    pub fn set_threshold(&mut self, threshold: usize) {
+        assert!(threshold > 0, "piles cannot topple without grains");
+        assert!(
+            self.model == Model::Manna || threshold >= self.moves().count(),
+            "a pile cannot give more grains than it has"
+        );
+        self.threshold = threshold;
+        for i in 0..self.hgt {
+            for j in 0..self.wth {
+                if self.is_unstable(i, j) {
+                    self.schedule.push_back((i, j));
+                    self.field[[i, j]].scheduled = true;
+                }
+            }
+        }
+    }
+
+    /// Replace the sources that `feed` uses, they must pass `Source::check`
+    /// and all their positions must be in the field
+    pub fn set_sources(&mut self, sources: Sources) -> Result<(), ParseError> {
+        for source in sources.sources() {
+            source
+                .check()
+                .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+        }
+        let outside = sources
+            .sources()
+            .iter()
+            .flat_map(Source::positions)
+            .find(|[i, j]| *i >= self.hgt || *j >= self.wth);
+        if let Some([i, j]) = outside {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "source at [{}, {}] out of the {}x{} field",
+                    i, j, self.hgt, self.wth
+                ),
+            )
+            .into());
         }
+        self.sources = sources;
+        Ok(())
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    /// Add the grains of all sources for one frame (without stabilizing)
+    pub fn feed(&mut self) {
+        for ([i, j], amount) in self.sources.next(self.hgt, self.wth) {
+            self.add(i, j, amount);
+        }
+    }
+
+    /// Change the colors of the piles, e.g. `Palette::AVALANCHE` to look at
+    /// unstable states
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Sandpile with the given number of grains on each pile, scheduled for
+    /// stabilization
+    pub fn from_config(config: &Configuration) -> Self {
+        let mut pile = Self::new(config.hgt, config.wth);
+        for i in 0..config.hgt {
+            for j in 0..config.wth {
+                pile.add(i, j, config.get(i, j));
+            }
+        }
+        pile
+    }
+
+    /// `[hgt, wth]`
+    pub fn dims(&self) -> [usize; 2] {
+        [self.hgt, self.wth]
+    }
+
+    /// Current number of grains on each pile
+    pub fn config(&self) -> Configuration {
+        Configuration::from_fn(self.hgt, self.wth, |i, j| self.field[[i, j]].hgt)
+    }
+
+    /// Write the current state to a single image (e.g. to look at a
+    /// configuration for which a video is not needed)
+    pub fn save_image(&self, file: &str) {
+        self.canvas().render(file);
+    }
+
+    /// Colors of the piles with the current palette
+    fn canvas(&self) -> Canvas<Shade> {
+        let mut canvas = Canvas::new(self.hgt, self.wth, Shade((0, 0, 0)));
+        for i in 0..self.hgt {
+            for j in 0..self.wth {
+                let g = &self.field[[i, j]];
+                canvas[[i, j]] = Shade(match self.palette.toppled {
+                    Some(c) if g.toppled => c,
+                    _ => (self.palette.map)(g.hgt, self.threshold),
+                });
+            }
+        }
+        canvas
+    }
+
+    /// Save the heights, the piles that are scheduled to topple and all the
+    /// rules, including the state of the random generators and the sources,
+    /// so that a long run can be resumed with `load` exactly as it would
+    /// have gone on. Only the palette is not saved.
+    ///
+    /// `*.txt` files are written as text: the same as `Configuration::save`,
+    /// followed by the lines
+    /// - `threshold t`
+    /// - `pending i1 j1 i2 j2 ...`
+    /// - `neighborhood vonneumann|moore|hex`
+    /// - `boundary sink|closed|torus i j`
+    /// - `model btw|manna|directed`
+    /// - `rng seed pos`: the generator of the Manna model, `pos` words into
+    ///   the stream of `seed`
+    /// - `source ...` for each source, as in `Sources::parse`
+    /// - `feed tick seed pos`: the frame and the generator of the sources
+    ///
+    /// Anything else is written in a compact binary format: `SAND`, then
+    /// `hgt`, `wth`, `threshold`, the heights line by line, the number of
+    /// pending piles and their positions, the index of the neighborhood,
+    /// the boundary (0 for `Sink`, 1 and the position for `Torus`, 2 for
+    /// `Closed`), the index of the model, the seed and position (low then
+    /// high 64 bits) of the generator, the frame, seed and position of the
+    /// sources and the length of their text, all as LEB128 varints, and
+    /// finally the text of the sources.
+    pub fn save(&self, file: &str) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(file)?);
+        let pending = self.schedule.iter().flat_map(|&(i, j)| vec![i, j]);
+        let neighborhood = NEIGHBORHOODS
+            .iter()
+            .position(|&(n, _)| n == self.neighborhood)
+            .unwrap();
+        let model = MODELS.iter().position(|&(m, _)| m == self.model).unwrap();
+        let pos = self.rng.get_word_pos();
+        let (tick, seed, feed) = self.sources.state();
+        let sources: Vec<_> = self
+            .sources
+            .sources()
+            .iter()
+            .map(Source::to_string)
+            .collect();
+        if file.ends_with(".txt") {
+            write!(f, "{}", self.config().to_text())?;
+            writeln!(f, "threshold {}", self.threshold)?;
+            let pending: Vec<_> = pending.map(|x| x.to_string()).collect();
+            writeln!(f, "pending {}", pending.join(" "))?;
+            writeln!(f, "neighborhood {}", NEIGHBORHOODS[neighborhood].1)?;
+            match self.boundary {
+                Boundary::Sink => writeln!(f, "boundary sink")?,
+                Boundary::Torus([i, j]) => writeln!(f, "boundary torus {} {}", i, j)?,
+                Boundary::Closed => writeln!(f, "boundary closed")?,
+            }
+            writeln!(f, "model {}", MODELS[model].1)?;
+            writeln!(f, "rng {} {}", self.seed, pos)?;
+            for source in &sources {
+                writeln!(f, "source {}", source)?;
+            }
+            writeln!(f, "feed {} {} {}", tick, seed, feed)?;
+        } else {
+            let mut out = b"SAND".to_vec();
+            let header = [self.hgt, self.wth, self.threshold];
+            let heights =
+                (0..self.hgt * self.wth).map(|k| self.field[[k / self.wth, k % self.wth]].hgt);
+            let pending = std::iter::once(self.schedule.len()).chain(pending);
+            let boundary = match self.boundary {
+                Boundary::Sink => vec![0],
+                Boundary::Torus([i, j]) => vec![1, i, j],
+                Boundary::Closed => vec![2],
+            };
+            let sources = sources.join("\n");
+            let numbers = header
+                .iter()
+                .copied()
+                .chain(heights)
+                .chain(pending)
+                .map(|x| x as u64)
+                .chain(std::iter::once(neighborhood as u64))
+                .chain(boundary.into_iter().map(|x| x as u64))
+                .chain(vec![
+                    model as u64,
+                    self.seed,
+                    pos as u64,
+                    (pos >> 64) as u64,
+                ])
+                .chain(vec![tick as u64, seed, feed as u64, (feed >> 64) as u64])
+                .chain(std::iter::once(sources.len() as u64));
+            for mut x in numbers {
+                while x >= 0x80 {
+                    out.push((x & 0x7f) as u8 | 0x80);
+                    x >>= 7;
+                }
+                out.push(x as u8);
+            }
+            out.extend(sources.bytes());
+            f.write_all(&out)?;
+        }
+        f.flush()
+    }
+
+    /// Read a file written by `save` (binary or text), or by
+    /// `Configuration::save` (in which case all unstable piles are
+    /// scheduled). The rules that the file does not give are the usual
+    /// ones, as in `new`.
+    pub fn load(file: &str) -> Result<Self, ParseError> {
+        let data = std::fs::read(file)?;
+        let (config, pending, rules) = if data.starts_with(b"SAND") {
+            let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+            let mut bytes = data[4..].iter();
+            let next = |bytes: &mut std::slice::Iter<u8>| -> io::Result<u64> {
+                let mut x = 0;
+                for shift in (0..64).step_by(7) {
+                    let b = *bytes.next().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "truncated sandpile")
+                    })?;
+                    x |= ((b & 0x7f) as u64) << shift;
+                    if b & 0x80 == 0 {
+                        return Ok(x);
+                    }
+                }
+                Err(invalid("number too large"))
+            };
+            let (hgt, wth) = (next(&mut bytes)? as usize, next(&mut bytes)? as usize);
+            let threshold = next(&mut bytes)? as usize;
+            // each height takes at least one byte
+            let size = hgt
+                .checked_mul(wth)
+                .filter(|&size| size <= data.len())
+                .ok_or_else(|| invalid("size larger than the file"))?;
+            let mut tab = Vec::with_capacity(size);
+            for _ in 0..size {
+                tab.push(next(&mut bytes)? as usize);
+            }
+            let mut pending = Vec::new();
+            for _ in 0..next(&mut bytes)? {
+                let (i, j) = (next(&mut bytes)? as usize, next(&mut bytes)? as usize);
+                if i >= hgt || j >= wth {
+                    return Err(invalid("pending pile out of the field").into());
+                }
+                pending.push((i, j));
+            }
+            let mut rules = Rules::new();
+            rules.threshold = Some(threshold);
+            // files saved before the rules were saved end here
+            if !bytes.as_slice().is_empty() {
+                let code = next(&mut bytes)? as usize;
+                rules.neighborhood = NEIGHBORHOODS
+                    .get(code)
+                    .ok_or_else(|| invalid("unknown neighborhood"))?
+                    .0;
+                rules.boundary = match next(&mut bytes)? {
+                    0 => Boundary::Sink,
+                    1 => Boundary::Torus([next(&mut bytes)? as usize, next(&mut bytes)? as usize]),
+                    2 => Boundary::Closed,
+                    _ => return Err(invalid("unknown boundary").into()),
+                };
+                let code = next(&mut bytes)? as usize;
+                rules.model = MODELS.get(code).ok_or_else(|| invalid("unknown model"))?.0;
+                let seed = next(&mut bytes)?;
+                let pos = next(&mut bytes)? as u128 | ((next(&mut bytes)? as u128) << 64);
+                rules.rng = (seed, pos);
+                let (tick, seed) = (next(&mut bytes)? as usize, next(&mut bytes)?);
+                let pos = next(&mut bytes)? as u128 | ((next(&mut bytes)? as u128) << 64);
+                let len = next(&mut bytes)? as usize;
+                if bytes.len() != len {
+                    return Err(invalid("sources do not fill the end of the file").into());
+                }
+                let text = std::str::from_utf8(bytes.as_slice())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                rules.sources = Sources::parse(text, seed)?.resume(tick, pos);
+            }
+            rules.check(hgt, wth).map_err(|(_, msg)| invalid(msg))?;
+            (Configuration { hgt, wth, tab }, Some(pending), rules)
+        } else {
+            let text = String::from_utf8(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut lines = significant_lines(&text);
+            let config = Configuration::parse_lines(&mut lines)?;
+            let (mut rules, mut pending) = (Rules::new(), None);
+            let (mut sources, mut feed) = (Vec::new(), (0, 0, 0));
+            // line of each key, for the errors found once all are read
+            let mut keys = Vec::new();
+            for (n, line) in lines {
+                let invalid = |msg: &str| ParseError::Invalid {
+                    line: n,
+                    msg: String::from(msg),
+                };
+                let mut words = line.split_whitespace();
+                let key = words.next().unwrap_or("");
+                let words: Vec<_> = words.collect();
+                let nums = || {
+                    words
+                        .iter()
+                        .map(|w| w.parse::<usize>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| invalid("expected a non-negative number"))
+                };
+                keys.push((key, n));
+                match key {
+                    "threshold" => match nums()?[..] {
+                        [t] => rules.threshold = Some(t),
+                        _ => return Err(invalid("expected `threshold t`")),
+                    },
+                    "pending" => {
+                        let v = nums()?;
+                        if v.len() % 2 != 0 {
+                            return Err(invalid("expected `pending i1 j1 i2 j2 ...`"));
+                        }
+                        if v.chunks(2)
+                            .any(|p| p[0] >= config.hgt || p[1] >= config.wth)
+                        {
+                            return Err(invalid("pending pile out of the field"));
+                        }
+                        pending = Some(v.chunks(2).map(|p| (p[0], p[1])).collect())
+                    }
+                    "neighborhood" => {
+                        rules.neighborhood = named(&NEIGHBORHOODS, &words)
+                            .ok_or_else(|| invalid("unknown neighborhood"))?
+                    }
+                    "model" => {
+                        rules.model =
+                            named(&MODELS, &words).ok_or_else(|| invalid("unknown model"))?
+                    }
+                    "boundary" => {
+                        rules.boundary = match words[..] {
+                            ["sink"] => Boundary::Sink,
+                            ["closed"] => Boundary::Closed,
+                            ["torus", i, j] => match (i.parse(), j.parse()) {
+                                (Ok(i), Ok(j)) => Boundary::Torus([i, j]),
+                                _ => return Err(invalid("expected a non-negative number")),
+                            },
+                            _ => return Err(invalid("expected `sink`, `closed` or `torus i j`")),
+                        }
+                    }
+                    "rng" => match words[..] {
+                        [seed, pos] => match (seed.parse(), pos.parse()) {
+                            (Ok(seed), Ok(pos)) => rules.rng = (seed, pos),
+                            _ => return Err(invalid("expected a non-negative number")),
+                        },
+                        _ => return Err(invalid("expected `rng seed pos`")),
+                    },
+                    "source" => {
+                        let source = match Sources::parse(&words.join(" "), 0) {
+                            Ok(parsed) => parsed.sources().first().cloned(),
+                            Err(ParseError::Invalid { msg, .. }) => return Err(invalid(&msg)),
+                            Err(e) => return Err(e),
+                        }
+                        .ok_or_else(|| invalid("expected a source"))?;
+                        let [hgt, wth] = config.dims();
+                        if source
+                            .positions()
+                            .iter()
+                            .any(|&[i, j]| i >= hgt || j >= wth)
+                        {
+                            return Err(invalid("source out of the field"));
+                        }
+                        sources.push(source);
+                    }
+                    "feed" => match words[..] {
+                        [tick, seed, pos] => match (tick.parse(), seed.parse(), pos.parse()) {
+                            (Ok(tick), Ok(seed), Ok(pos)) => feed = (tick, seed, pos),
+                            _ => return Err(invalid("expected a non-negative number")),
+                        },
+                        _ => return Err(invalid("expected `feed tick seed pos`")),
+                    },
+                    _ => {
+                        return Err(invalid(
+                            "expected `threshold`, `pending`, `neighborhood`, `boundary`, \
+                             `model`, `rng`, `source` or `feed`",
+                        ))
+                    }
+                }
+            }
+            let (tick, seed, pos) = feed;
+            rules.sources = Sources::new(seed);
+            for source in sources {
+                rules.sources = rules.sources.with(source)?;
+            }
+            rules.sources = rules.sources.resume(tick, pos);
+            rules.check(config.hgt, config.wth).map_err(|(key, msg)| {
+                let line = keys.iter().rev().find(|&&(k, _)| k == key).map(|&(_, n)| n);
+                ParseError::Invalid {
+                    line: line.unwrap_or(0),
+                    msg: String::from(msg),
+                }
+            })?;
+            (config, pending, rules)
+        };
+        let mut pile = Self::with(config.hgt, config.wth, rules.neighborhood, rules.boundary);
+        pile.model = rules.model;
+        pile.threshold = rules.threshold.unwrap_or_else(|| pile.moves().count());
+        pile.seed = rules.rng.0;
+        pile.rng = generator(rules.rng.0, rules.rng.1);
+        pile.set_sources(rules.sources)?;
+        for i in 0..config.hgt {
+            for j in 0..config.wth {
+                pile.field[[i, j]].hgt = config.get(i, j);
+            }
+        }
+        let pending = pending.unwrap_or_else(|| {
+            (0..config.hgt * config.wth)
+                .map(|k| (k / config.wth, k % config.wth))
+                .filter(|&(i, j)| pile.is_unstable(i, j))
+                .collect()
+        });
+        for (i, j) in pending {
+            if !pile.field[[i, j]].scheduled {
+                pile.schedule.push_back((i, j));
+                pile.field[[i, j]].scheduled = true;
+            }
+        }
+        Ok(pile)
     }
 
     /// Check that no overflow occurs when looking at a neighbor
@@ -126,20 +792,84 @@ impl Sandpile {
         [i, j]
     }
 
+    /// Piles that receive grains when `[i, j]` topples, written to `out`
+    /// (`None` when the grains are lost). Returns how many there are.
+    fn receivers(&self, i: usize, j: usize, out: &mut [Option<[usize; 2]>; 8]) -> usize {
+        let mut n = 0;
+        for &(mvi, mvj) in self.moves() {
+            let target = match self.boundary {
+                Boundary::Sink if self.is_valid_move(i, j, mvi, mvj) => {
+                    Some(self.index_move(i, j, mvi, mvj))
+                }
+                Boundary::Sink => None,
+                Boundary::Torus(sink) => {
+                    Some(self.index_move(i, j, mvi, mvj)).filter(|&t| t != sink)
+                }
+                Boundary::Closed if self.is_valid_move(i, j, mvi, mvj) => {
+                    Some(self.index_move(i, j, mvi, mvj))
+                }
+                Boundary::Closed => Some([i, j]),
+            };
+            out[n] = target;
+            n += 1;
+        }
+        n
+    }
+
+    /// How many grains each of the `n` receivers gets when a pile topples
+    /// `fall` times
+    fn shares(&mut self, n: usize, fall: usize) -> [usize; 8] {
+        let mut shares = [0; 8];
+        match self.model {
+            Model::Btw | Model::Directed => {
+                for s in &mut shares[..n] {
+                    *s = fall;
+                }
+            }
+            Model::Manna => {
+                for _ in 0..n.min(self.threshold) * fall {
+                    shares[self.rng.gen_range(0, n)] += 1;
+                }
+            }
+        }
+        shares
+    }
+
     /// Collapse a single pile when it has too many grains
     fn topple(&mut self, i: usize, j: usize) {
-        let fall = self.field[[i, j]].hgt / 4;
+        let fall = self.field[[i, j]].hgt / self.threshold;
+        self.collapse(i, j, fall);
+    }
+
+    /// Topple a pile only once even if it has enough grains for several
+    /// topples, and schedule it again if it is still unstable
+    fn topple_once(&mut self, i: usize, j: usize) {
+        let fall = (self.field[[i, j]].hgt / self.threshold).min(1);
+        self.collapse(i, j, fall);
+        if self.is_unstable(i, j) {
+            self.schedule.push_back((i, j));
+            self.field[[i, j]].scheduled = true;
+        }
+    }
+
+    /// Topple a pile `fall` times at once
+    fn collapse(&mut self, i: usize, j: usize, fall: usize) {
         self.field[[i, j]].scheduled = false;
         if fall > 0 {
-            self.field[[i, j]].hgt -= fall * 4;
-            for (mvi, mvj) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
-                if self.is_valid_move(i, j, *mvi, *mvj) {
-                    let [ni, nj] = self.index_move(i, j, *mvi, *mvj);
-                    self.field[[ni, nj]].hgt += fall;
-                    if self.is_unstable(ni, nj) {
-                        self.schedule.push_back((ni, nj));
-                        self.field[[ni, nj]].scheduled = true;
-                    }
+            self.field[[i, j]].hgt -= fall * self.threshold;
+            self.field[[i, j]].toppled = true;
+            let mut targets = [None; 8];
+            let n = self.receivers(i, j, &mut targets);
+            let shares = self.shares(n, fall);
+            for (k, &[ni, nj]) in targets[..n]
+                .iter()
+                .enumerate()
+                .filter_map(|(k, t)| t.as_ref().map(|t| (k, t)))
+            {
+                self.field[[ni, nj]].hgt += shares[k];
+                if self.is_unstable(ni, nj) {
+                    self.schedule.push_back((ni, nj));
+                    self.field[[ni, nj]].scheduled = true;
                 }
             }
         }
@@ -147,7 +877,9 @@ impl Sandpile {
 
     /// Collapse all scheduled piles until no more topples can occur.
     ///
-    /// Note that this might loop forever if the canvas is full
+    /// Note that this might loop forever if the canvas is full and no
+    /// grain can be lost (closed boundary, and threshold equal to the
+    /// number of neighbors)
     pub fn stabilize(&mut self) {
         loop {
             match self.schedule.pop_front() {
@@ -160,17 +892,206 @@ impl Sandpile {
         }
     }
 
+    /// Do a part of `stabilize`. Returns whether some piles are still
+    /// unstable.
+    ///
+    /// Unlike in `stabilize`, a pile topples only once per step: a pile
+    /// with enough grains for several topples is scheduled again, so that
+    /// a large heap spreads over several frames.
+    pub fn step(&mut self, step: Step) -> bool {
+        let n = match step {
+            Step::Wave => self.schedule.len(),
+            Step::Topples(n) => n.max(1),
+        };
+        for _ in 0..n {
+            match self.schedule.pop_front() {
+                None => break,
+                Some((i, j)) => {
+                    self.topple_once(i, j);
+                    self.cnt += 1;
+                }
+            }
+        }
+        !self.schedule.is_empty()
+    }
+
+    /// Stabilize and render a frame after each step, to show how an
+    /// avalanche spreads (best seen with `Palette::AVALANCHE`).
+    /// The last frame shows the stable sandpile.
+    pub fn stabilize_render(&mut self, cfg: &mut crate::Config, step: Step) {
+        while self.step(step) {
+            self.render(cfg);
+        }
+        self.render(cfg);
+    }
+
+    /// Same result as `stabilize` (the order of topples does not matter in
+    /// an abelian sandpile), but much faster when huge amounts of grains
+    /// are involved.
+    ///
+    /// Instead of following the schedule one pile at a time, the rectangle
+    /// that contains all unstable piles is swept line by line, and each
+    /// pile topples as many times at once as it can (`hgt / 4`).
+    /// The rectangle is updated after each sweep.
+    ///
+    /// Only for the usual rules, other sandpiles are stabilized normally.
+    pub fn stabilize_bulk(&mut self) {
+        if (self.threshold, self.neighborhood, self.boundary, self.model)
+            != (4, Neighborhood::VonNeumann, Boundary::Sink, Model::Btw)
+        {
+            return self.stabilize();
+        }
+        let (hgt, wth) = (self.hgt, self.wth);
+        // surrounded by a border of width 1 where the grains that fall off
+        // are lost, so that the inner loop needs no bound checks
+        let pw = wth + 2;
+        let mut h = vec![0; (hgt + 2) * pw];
+        let mut toppled = vec![false; (hgt + 2) * pw];
+        for i in 0..hgt {
+            for j in 0..wth {
+                h[(i + 1) * pw + j + 1] = self.field[[i, j]].hgt;
+            }
+        }
+        // piles that may topple are in lines imin..imax, columns jmin..jmax
+        let (mut imin, mut imax, mut jmin, mut jmax) = (1, hgt + 1, 1, wth + 1);
+        while imin < imax {
+            let (mut nimin, mut nimax, mut njmin, mut njmax) = (hgt + 1, 0, wth + 1, 0);
+            for i in imin..imax {
+                let row = i * pw;
+                for k in row + jmin..row + jmax {
+                    let fall = h[k] / 4;
+                    if fall == 0 {
+                        continue;
+                    }
+                    h[k] %= 4;
+                    h[k - pw] += fall;
+                    h[k + pw] += fall;
+                    h[k - 1] += fall;
+                    h[k + 1] += fall;
+                    toppled[k] = true;
+                    self.cnt += 1;
+                    nimin = nimin.min(i);
+                    nimax = nimax.max(i + 1);
+                    njmin = njmin.min(k - row);
+                    njmax = njmax.max(k - row + 1);
+                }
+            }
+            // neighbors of toppled piles may have become unstable, but the
+            // border never topples
+            imin = nimin.max(2) - 1;
+            imax = (nimax + 1).min(hgt + 1);
+            jmin = njmin.max(2) - 1;
+            jmax = (njmax + 1).min(wth + 1);
+        }
+        for i in 0..hgt {
+            for j in 0..wth {
+                self.field[[i, j]].hgt = h[(i + 1) * pw + j + 1];
+                self.field[[i, j]].toppled |= toppled[(i + 1) * pw + j + 1];
+                self.field[[i, j]].scheduled = false;
+            }
+        }
+        self.schedule.clear();
+    }
+
+    /// Drop `grains` grains on the center of an empty sandpile large enough
+    /// that none of them fall off the border, and stabilize it.
+    /// The well-known fractal shows from about `1 << 16` grains; in a
+    /// release build `1 << 20` grains take about a second and `1 << 24`
+    /// about three minutes.
+    ///
+    /// The result is symmetric, so only a quarter of it is computed. Above
+    /// `MULTISCALE` grains, the pile for a quarter of the grains is computed
+    /// first and scaled up to guess how many times each pile topples, and
+    /// the guess is then corrected into the exact result (see `Quarter`).
+    pub fn single_source(grains: usize) -> Self {
+        let quarter = Quarter::stabilize(grains);
+        let side = quarter.side;
+        let size = 2 * side - 1;
+        let mut pile = Self::new(size, size);
+        for i in 0..size {
+            for j in 0..size {
+                let qi = (i as isize - side as isize + 1).unsigned_abs();
+                let qj = (j as isize - side as isize + 1).unsigned_abs();
+                pile.field[[i, j]].hgt = quarter.h[qi * (side + 1) + qj] as usize;
+            }
+        }
+        pile
+    }
+
+    /// Drop a single grain on a stable sandpile and relax it wave by wave,
+    /// keeping track of the avalanche that this causes.
+    pub fn drop_grain(&mut self, i: usize, j: usize) -> Avalanche {
+        self.stabilize();
+        let mut aval = Avalanche {
+            pos: [i, j],
+            size: 0,
+            area: 0,
+            waves: 0,
+            radius: 0.,
+        };
+        if self.boundary == Boundary::Torus([i, j]) {
+            return aval;
+        }
+        let mut toppled = vec![false; self.hgt * self.wth];
+        let mut queue = Vec::new();
+        let mut targets = [None; 8];
+        self.field[[i, j]].hgt += 1;
+        while self.field[[i, j]].hgt >= self.threshold {
+            aval.waves += 1;
+            queue.push((i, j));
+            // within a wave, every pile topples at most once (except in the
+            // Manna model)
+            while let Some((ci, cj)) = queue.pop() {
+                if self.field[[ci, cj]].hgt < self.threshold {
+                    continue;
+                }
+                self.field[[ci, cj]].hgt -= self.threshold;
+                self.field[[ci, cj]].toppled = true;
+                aval.size += 1;
+                if !toppled[ci * self.wth + cj] {
+                    toppled[ci * self.wth + cj] = true;
+                    aval.area += 1;
+                    let (di, dj) = (ci as f64 - i as f64, cj as f64 - j as f64);
+                    aval.radius = aval.radius.max((di * di + dj * dj).sqrt());
+                }
+                let n = self.receivers(ci, cj, &mut targets);
+                let shares = self.shares(n, 1);
+                for (k, &[ni, nj]) in targets[..n]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, t)| t.as_ref().map(|t| (k, t)))
+                {
+                    self.field[[ni, nj]].hgt += shares[k];
+                    if self.field[[ni, nj]].hgt >= self.threshold && (ni, nj) != (i, j) {
+                        queue.push((ni, nj));
+                    }
+                }
+                if self.field[[ci, cj]].hgt >= self.threshold && (ci, cj) != (i, j) {
+                    queue.push((ci, cj));
+                }
+            }
+        }
+        self.cnt += aval.size;
+        aval
+    }
+
     /// Before scheduling a pile for topple, we check that it is neither
     /// not high enough, nor already scheduled
     fn is_unstable(&self, i: usize, j: usize) -> bool {
         let g = &self.field[[i, j]];
-        g.hgt > 3 && !g.scheduled
+        g.hgt >= self.threshold && !g.scheduled
     }
 
-    /// Print output to file
+    /// Print output to file, piles that toppled are highlighted until the
+    /// next frame
     pub fn render(&mut self, cfg: &mut crate::Config) {
         let name = cfg.frame();
-        self.field.render(&name);
+        self.canvas().render(&name);
+        for i in 0..self.hgt {
+            for j in 0..self.wth {
+                self.field[[i, j]].toppled = false;
+            }
+        }
 
         eprint!("\rDone rendering frame {} : workload {}", name, self.cnt);
         self.cnt = 0;
@@ -178,6 +1099,9 @@ impl Sandpile {
 
     /// Conditionally schedule a pile for topple
     pub fn add(&mut self, i: usize, j: usize, amount: usize) {
+        if self.boundary == Boundary::Torus([i, j]) {
+            return;
+        }
         self.field[[i, j]].hgt += amount;
         if self.is_unstable(i, j) {
             self.schedule.push_back((i, j));
@@ -185,3 +1109,1208 @@ impl Sandpile {
         }
     }
 }
+
+/// Rules of a pile read by `Sandpile::load`, besides its heights
+struct Rules {
+    neighborhood: Neighborhood,
+    boundary: Boundary,
+    model: Model,
+    threshold: Option<usize>,
+    /// Seed and position of the generator of the Manna model
+    rng: (u64, u128),
+    sources: Sources,
+}
+
+impl Rules {
+    /// The usual rules, for the files that do not give them
+    fn new() -> Self {
+        Self {
+            neighborhood: Neighborhood::VonNeumann,
+            boundary: Boundary::Sink,
+            model: Model::Btw,
+            threshold: None,
+            rng: (0, 0),
+            sources: Sources::new(0),
+        }
+    }
+
+    /// Check the rules together (as `set_model` and `set_threshold` do) on a
+    /// `hgt x wth` field, or return the key of the text format at fault and
+    /// why
+    fn check(&self, hgt: usize, wth: usize) -> Result<(), (&'static str, &'static str)> {
+        let receivers = self.neighborhood.receivers(self.model);
+        match (self.boundary, self.threshold) {
+            _ if self.model == Model::Directed && self.neighborhood == Neighborhood::VonNeumann => {
+                Err((
+                    "model",
+                    "the directed model needs more than one neighbor below",
+                ))
+            }
+            (Boundary::Torus([i, j]), _) if i >= hgt || j >= wth => {
+                Err(("boundary", "pile out of the field"))
+            }
+            (_, Some(0)) => Err(("threshold", "piles cannot topple without grains")),
+            (_, Some(t)) if t < receivers && self.model != Model::Manna => {
+                Err(("threshold", "threshold lower than the number of neighbors"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Value with the name `words` in `names`
+fn named<T: Copy>(names: &[(T, &str)], words: &[&str]) -> Option<T> {
+    names
+        .iter()
+        .find(|&&(_, name)| words == [name])
+        .map(|&(x, _)| x)
+}
+
+/// Number of grains from which `single_source` guesses the odometer from the
+/// pile with a quarter of the grains, instead of toppling from scratch
+const MULTISCALE: usize = 1 << 12;
+
+/// Part of the excess of the coarser pile that `single_source` takes off its
+/// guess (see `Quarter::excess`)
+const DAMPING: f64 = 0.75;
+
+/// Quarter of the pile of `Sandpile::single_source`: piles `[0, side)` in
+/// both directions, where line and column 0 are the symmetry axes, plus one
+/// extra line and column that collect the grains reaching the border.
+///
+/// The result does not depend on the order of the topples, and it only
+/// takes the number of times each pile topples (the odometer) to compute
+/// it. Any odometer that leaves no pile with more than `MAX_STABLE` grains
+/// is at least the true one, so a guess (see `odometer`) can be corrected
+/// exactly: topple until stable, then untopple while some piles can (see
+/// `burn`). The closer the guess, the fewer the corrections.
+struct Quarter {
+    side: usize,
+    /// Heights, negative when a guess toppled a pile too many times
+    h: Vec<i64>,
+    /// Number of times each pile toppled
+    w: Vec<i64>,
+    /// How much the odometer guessed by `odometer` exceeded the exact one,
+    /// as a multiple of `dome`: the pile with a quarter of the grains is a
+    /// little less dense in its middle, which shows as a paraboloid.
+    /// 0 if there was no guess.
+    excess: f64,
+}
+
+impl Quarter {
+    /// Stabilize `grains` grains dropped on `[0, 0]` of a quarter large
+    /// enough to keep them all
+    fn stabilize(grains: usize) -> Self {
+        let coarse = if grains >= MULTISCALE {
+            Some(Self::stabilize(grains / 4))
+        } else {
+            None
+        };
+        // the final pile is roughly a disk of average height 2.1
+        let mut side = ((grains as f64).sqrt() * 0.4) as usize + 2;
+        loop {
+            if let Some(quarter) = Self::relax(grains, side, coarse.as_ref()) {
+                return quarter;
+            }
+            side *= 2;
+        }
+    }
+
+    /// Stabilize in a quarter of side `side`, starting from the odometer
+    /// guessed from `coarse` if any.
+    /// Returns `None` if some grains reach the border.
+    fn relax(grains: usize, side: usize, coarse: Option<&Self>) -> Option<Self> {
+        let pw = side + 1;
+        let mut quarter = Self {
+            side,
+            h: vec![0; pw * pw],
+            w: vec![0; pw * pw],
+            excess: 0.,
+        };
+        quarter.h[0] = grains as i64;
+        let mut guess = None;
+        if let Some(coarse) = coarse {
+            let odometer = coarse.odometer(grains, side);
+            let dome = dome(&odometer, side);
+            // the excess hardly changes from one scale to the next, but a
+            // guess too low takes longer to correct than one too high
+            for k in 0..side * side {
+                let fall = odometer[k] - DAMPING * coarse.excess * dome[k];
+                quarter.topple(k / side * pw + k % side, fall.max(0.) as i64);
+            }
+            guess = Some((odometer, dome));
+        }
+        quarter.sweep();
+        let (mut load, mut stack) = (Vec::new(), Vec::new());
+        loop {
+            quarter.untopple();
+            if quarter.burn(&mut load, &mut stack) == 0 {
+                break;
+            }
+        }
+        if let Some((odometer, dome)) = guess {
+            // fit the error of the guess to the dome, by least squares
+            let (mut dot, mut norm) = (0., 0.);
+            for k in 0..side * side {
+                let error =
+                    odometer[k].max(0.).floor() - quarter.w[k / side * pw + k % side] as f64;
+                dot += error * dome[k];
+                norm += dome[k] * dome[k];
+            }
+            if norm > 0. {
+                quarter.excess = dot / norm;
+            }
+        }
+        let h = &quarter.h;
+        if (0..pw).any(|k| h[side * pw + k] > 0 || h[k * pw + side] > 0) {
+            None
+        } else {
+            Some(quarter)
+        }
+    }
+
+    /// Topple pile `k` `fall` times (untopple it if `fall` is negative)
+    fn topple(&mut self, k: usize, fall: i64) {
+        let pw = self.side + 1;
+        let (i, j) = (k / pw, k % pw);
+        let h = &mut self.h;
+        self.w[k] += fall;
+        h[k] -= 4 * fall;
+        h[k + pw] += fall;
+        h[k + 1] += fall;
+        // the pile on the other side of the axis topples in the same way, so
+        // the axis receives grains from both sides
+        match i {
+            0 => (),
+            1 => h[k - pw] += 2 * fall,
+            _ => h[k - pw] += fall,
+        }
+        match j {
+            0 => (),
+            1 => h[k - 1] += 2 * fall,
+            _ => h[k - 1] += fall,
+        }
+    }
+
+    /// Topple all unstable piles at once until none is left, within the
+    /// bounds of the piles that toppled in the previous sweep (as in
+    /// `stabilize_bulk`)
+    fn sweep(&mut self) {
+        let (side, pw) = (self.side, self.side + 1);
+        let (mut imin, mut imax, mut jmin, mut jmax) = (0, side, 0, side);
+        while imin < imax {
+            let (mut nimin, mut nimax, mut njmin, mut njmax) = (side, 0, side, 0);
+            for i in imin..imax {
+                for j in jmin..jmax {
+                    let fall = self.h[i * pw + j] / 4;
+                    if fall <= 0 {
+                        continue;
+                    }
+                    self.topple(i * pw + j, fall);
+                    nimin = nimin.min(i);
+                    nimax = nimax.max(i + 1);
+                    njmin = njmin.min(j);
+                    njmax = njmax.max(j + 1);
+                }
+            }
+            imin = nimin.max(1) - 1;
+            imax = (nimax + 1).min(side);
+            jmin = njmin.max(1) - 1;
+            jmax = (njmax + 1).min(side);
+        }
+    }
+
+    /// Untopple the piles with a negative height, just enough to make them
+    /// non-negative: they cannot have toppled that many times, whatever
+    /// their neighbors do
+    fn untopple(&mut self) {
+        let (side, pw) = (self.side, self.side + 1);
+        let mut any = true;
+        while any {
+            any = false;
+            for i in 0..side {
+                for j in 0..side {
+                    let k = i * pw + j;
+                    if self.h[k] < 0 {
+                        self.topple(k, -(3 - self.h[k]) / 4);
+                        any = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Untopple once the largest set of piles that would all stay stable
+    /// (counting the grains that they stop receiving from each other), and
+    /// return its size. The odometer is exact once this set is empty.
+    ///
+    /// The set is found by burning: piles that never toppled are burnt, and
+    /// so is any pile that would get more than `MAX_STABLE` grains after
+    /// losing those of its burnt neighbors. `load` and `stack` are only
+    /// buffers.
+    fn burn(&mut self, load: &mut Vec<i64>, stack: &mut Vec<usize>) -> usize {
+        const BURNT: i64 = i64::MIN / 2;
+        let (side, pw) = (self.side, self.side + 1);
+        let max = MAX_STABLE as i64;
+        load.clear();
+        load.extend(self.w.iter().map(|&w| if w == 0 { BURNT } else { 0 }));
+        // piles that send grains to `k`, the border never topples
+        for i in 0..side {
+            for j in 0..side {
+                let k = i * pw + j;
+                if load[k] == BURNT {
+                    continue;
+                }
+                let senders = [
+                    k + pw,
+                    if i == 0 { k + pw } else { k - pw },
+                    k + 1,
+                    if j == 0 { k + 1 } else { k - 1 },
+                ];
+                let missing = senders.iter().filter(|&&s| load[s] == BURNT).count();
+                load[k] = self.h[k] + missing as i64;
+                if load[k] > max {
+                    stack.push(k);
+                }
+            }
+        }
+        while let Some(k) = stack.pop() {
+            if load[k] == BURNT {
+                continue;
+            }
+            load[k] = BURNT;
+            let (i, j) = (k / pw, k % pw);
+            let mut receive = |r: usize, n: i64| {
+                if load[r] != BURNT {
+                    load[r] += n;
+                    if load[r] > max {
+                        stack.push(r);
+                    }
+                }
+            };
+            receive(k + pw, 1);
+            receive(k + 1, 1);
+            if i > 0 {
+                receive(k - pw, if i == 1 { 2 } else { 1 });
+            }
+            if j > 0 {
+                receive(k - 1, if j == 1 { 2 } else { 1 });
+            }
+        }
+        let mut unburnt = 0;
+        for i in 0..side {
+            for j in 0..side {
+                if load[i * pw + j] != BURNT {
+                    self.topple(i * pw + j, -1);
+                    unburnt += 1;
+                }
+            }
+        }
+        unburnt
+    }
+
+    /// Guess the odometer of `grains` grains in a quarter of side `side`
+    /// from this pile, stabilized with a quarter of the grains.
+    ///
+    /// Twice larger, the final heights look the same, so the odometer `u`
+    /// nearly solves `4 u - (sum of u on the neighbors) = s - h`, where `s`
+    /// is the grains dropped and `h` this pile scaled up. The equation is
+    /// solved with multigrid V-cycles.
+    fn odometer(&self, grains: usize, side: usize) -> Vec<f64> {
+        let pw = self.side + 1;
+        let at = |a: usize, b: usize| {
+            if a < self.side && b < self.side {
+                self.h[a * pw + b] as f64
+            } else {
+                0.
+            }
+        };
+        // halved down to at most 8 for the coarser grids
+        let mut n = side;
+        let mut scale = 1;
+        while n > 8 {
+            n = n.div_ceil(2);
+            scale *= 2;
+        }
+        let n = n * scale;
+        let mut f = vec![0.; n * n];
+        for i in 0..n {
+            for j in 0..n {
+                let (a, b) = (i / 2, j / 2);
+                let h = match (i % 2, j % 2) {
+                    (0, 0) => at(a, b),
+                    (1, 0) => (at(a, b) + at(a + 1, b)) / 2.,
+                    (0, 1) => (at(a, b) + at(a, b + 1)) / 2.,
+                    _ => (at(a, b) + at(a + 1, b) + at(a, b + 1) + at(a + 1, b + 1)) / 4.,
+                };
+                f[i * n + j] = -h;
+            }
+        }
+        f[0] += grains as f64;
+        let mut u = vec![0.; n * n];
+        for _ in 0..100 {
+            if vcycle(&mut u, &f, n) < 0.05 {
+                break;
+            }
+        }
+        (0..side * side)
+            .map(|k| u[k / side * n + k % side])
+            .collect()
+    }
+}
+
+/// `r^2 - i^2 - j^2` at `[i, j]` (0 beyond `r`), where `r` is the radius of
+/// the disk where `odometer` is positive, on a quarter of side `side`
+fn dome(odometer: &[f64], side: usize) -> Vec<f64> {
+    let square = |k: usize| ((k / side).pow(2) + (k % side).pow(2)) as f64;
+    let r2 = (0..side * side)
+        .filter(|&k| odometer[k] >= 1.)
+        .map(square)
+        .fold(0., f64::max);
+    (0..side * side).map(|k| (r2 - square(k)).max(0.)).collect()
+}
+
+/// Value of `u` on an `n x n` quarter at `[i, j]`, mirrored across the axes
+/// and zero beyond the border
+fn mirrored(u: &[f64], n: usize, i: isize, j: isize) -> f64 {
+    let (i, j) = (i.unsigned_abs(), j.unsigned_abs());
+    if i >= n || j >= n {
+        0.
+    } else {
+        u[i * n + j]
+    }
+}
+
+/// `4 u - (sum of u on the neighbors)` at `[i, j]`
+fn laplacian(u: &[f64], n: usize, i: isize, j: isize) -> f64 {
+    4. * mirrored(u, n, i, j)
+        - mirrored(u, n, i + 1, j)
+        - mirrored(u, n, i - 1, j)
+        - mirrored(u, n, i, j + 1)
+        - mirrored(u, n, i, j - 1)
+}
+
+/// Gauss-Seidel sweeps towards the solution of `laplacian(u) = f`, in
+/// red-black order. Returns the largest change of a value.
+fn smooth(u: &mut [f64], f: &[f64], n: usize, sweeps: usize) -> f64 {
+    let mut change: f64 = 0.;
+    for _ in 0..sweeps {
+        for color in 0..2 {
+            for i in 0..n {
+                for j in ((i + color) % 2..n).step_by(2) {
+                    let (a, b) = (i as isize, j as isize);
+                    let sum = mirrored(u, n, a + 1, b)
+                        + mirrored(u, n, a - 1, b)
+                        + mirrored(u, n, a, b + 1)
+                        + mirrored(u, n, a, b - 1);
+                    let new = (sum + f[i * n + j]) / 4.;
+                    change = change.max((new - u[i * n + j]).abs());
+                    u[i * n + j] = new;
+                }
+            }
+        }
+    }
+    change
+}
+
+/// One multigrid V-cycle towards the solution of `laplacian(u) = f` on an
+/// `n x n` quarter. Returns the largest change of the last smoothing (0 on
+/// the coarsest grid, which is solved exactly).
+fn vcycle(u: &mut [f64], f: &[f64], n: usize) -> f64 {
+    if n <= 8 {
+        // small enough to solve exactly
+        for _ in 0..10_000 {
+            if smooth(u, f, n, 1) < 1e-9 {
+                break;
+            }
+        }
+        return 0.;
+    }
+    smooth(u, f, n, 2);
+    // the residual, restricted to a grid twice coarser (where a cell covers
+    // 4 cells, so that the laplacian is 4 times smaller)
+    let nc = n / 2;
+    let mut fc = vec![0.; nc * nc];
+    for a in 0..nc {
+        for b in 0..nc {
+            let mut sum = 0.;
+            for di in -1..=1 {
+                for dj in -1..=1 {
+                    let (i, j) = (2 * a as isize + di, 2 * b as isize + dj);
+                    let residual = mirrored(f, n, i, j) - laplacian(u, n, i, j);
+                    sum += residual * ((2 - di.abs()) * (2 - dj.abs())) as f64;
+                }
+            }
+            fc[a * nc + b] = sum / 4.;
+        }
+    }
+    let mut uc = vec![0.; nc * nc];
+    vcycle(&mut uc, &fc, nc);
+    for i in 0..n {
+        for j in 0..n {
+            let (a, b) = ((i / 2) as isize, (j / 2) as isize);
+            u[i * n + j] += match (i % 2, j % 2) {
+                (0, 0) => mirrored(&uc, nc, a, b),
+                (1, 0) => (mirrored(&uc, nc, a, b) + mirrored(&uc, nc, a + 1, b)) / 2.,
+                (0, 1) => (mirrored(&uc, nc, a, b) + mirrored(&uc, nc, a, b + 1)) / 2.,
+                _ => {
+                    (mirrored(&uc, nc, a, b)
+                        + mirrored(&uc, nc, a + 1, b)
+                        + mirrored(&uc, nc, a, b + 1)
+                        + mirrored(&uc, nc, a + 1, b + 1))
+                        / 4.
+                }
+            };
+        }
+    }
+    smooth(u, f, n, 2)
+}
+
+/// Number of grains on each pile of a grid, as a value independent of any
+/// simulation.
+///
+/// Stable recurrent configurations form the sandpile group, for which `+`
+/// is the group operation (addition pile by pile, then stabilization).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Configuration {
+    hgt: usize,
+    wth: usize,
+    tab: Vec<usize>,
+}
+
+impl Configuration {
+    /// Configuration where the pile at `[i, j]` has `f(i, j)` grains
+    pub fn from_fn<F: Fn(usize, usize) -> usize>(hgt: usize, wth: usize, f: F) -> Self {
+        Self {
+            hgt,
+            wth,
+            tab: (0..hgt * wth).map(|k| f(k / wth, k % wth)).collect(),
+        }
+    }
+
+    /// Configuration with `amount` grains on every pile
+    pub fn filled(hgt: usize, wth: usize, amount: usize) -> Self {
+        Self::from_fn(hgt, wth, |_, _| amount)
+    }
+
+    /// Highest stable configuration: `MAX_STABLE` everywhere
+    pub fn max_stable(hgt: usize, wth: usize) -> Self {
+        Self::filled(hgt, wth, MAX_STABLE)
+    }
+
+    /// `2m - stab(2m)` where `m` is the maximal stable configuration.
+    ///
+    /// It is equivalent to the empty configuration (it only differs from it
+    /// by topples) and has at least `MAX_STABLE` grains on every pile, so
+    /// adding it to any configuration and stabilizing gives a recurrent
+    /// configuration of the same class.
+    fn zero(hgt: usize, wth: usize) -> Self {
+        let double = Self::filled(hgt, wth, 2 * MAX_STABLE);
+        let stable = double.stabilize();
+        Self::from_fn(hgt, wth, |i, j| double.get(i, j) - stable.get(i, j))
+    }
+
+    /// Identity element of the sandpile group of a `hgt x wth` grid:
+    /// the unique recurrent configuration `e` such that `e + c = c` for
+    /// all recurrent `c`.
+    ///
+    /// Computed as `stab(2m - stab(2m))`.
+    pub fn identity(hgt: usize, wth: usize) -> Self {
+        Self::zero(hgt, wth).stabilize()
+    }
+
+    /// Number of grains on a pile
+    pub fn get(&self, i: usize, j: usize) -> usize {
+        self.tab[i * self.wth + j]
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, amount: usize) {
+        self.tab[i * self.wth + j] = amount;
+    }
+
+    /// `[hgt, wth]`
+    pub fn dims(&self) -> [usize; 2] {
+        [self.hgt, self.wth]
+    }
+
+    /// Topple until no pile has more than `MAX_STABLE` grains
+    pub fn stabilize(&self) -> Self {
+        let mut pile = Sandpile::from_config(self);
+        pile.stabilize_bulk();
+        pile.config()
+    }
+
+    /// Check that no pile can topple
+    pub fn is_stable(&self) -> bool {
+        self.tab.iter().all(|&h| h <= MAX_STABLE)
+    }
+
+    /// Recurrent configurations are the elements of the sandpile group:
+    /// they are stable and unchanged by the addition of the identity.
+    ///
+    /// Checked with Dhar's burning algorithm: the fire starts from the
+    /// border and a pile burns once it has at least as many grains as
+    /// unburnt neighbors. The configuration is recurrent iff everything
+    /// burns.
+    pub fn is_recurrent(&self) -> bool {
+        if !self.is_stable() {
+            return false;
+        }
+        let neighbors = |i: usize, j: usize| {
+            let mut v = Vec::with_capacity(4);
+            if i > 0 {
+                v.push([i - 1, j]);
+            }
+            if i + 1 < self.hgt {
+                v.push([i + 1, j]);
+            }
+            if j > 0 {
+                v.push([i, j - 1]);
+            }
+            if j + 1 < self.wth {
+                v.push([i, j + 1]);
+            }
+            v
+        };
+        let mut unburnt: Vec<usize> = (0..self.hgt * self.wth)
+            .map(|k| neighbors(k / self.wth, k % self.wth).len())
+            .collect();
+        let mut burnt = vec![false; self.hgt * self.wth];
+        let mut fire: Vec<[usize; 2]> = (0..self.hgt * self.wth)
+            .filter(|&k| self.tab[k] >= unburnt[k])
+            .map(|k| [k / self.wth, k % self.wth])
+            .collect();
+        for &[i, j] in &fire {
+            burnt[i * self.wth + j] = true;
+        }
+        let mut cnt = 0;
+        while let Some([i, j]) = fire.pop() {
+            cnt += 1;
+            for [ni, nj] in neighbors(i, j) {
+                let k = ni * self.wth + nj;
+                unburnt[k] -= 1;
+                if !burnt[k] && self.tab[k] >= unburnt[k] {
+                    burnt[k] = true;
+                    fire.push([ni, nj]);
+                }
+            }
+        }
+        cnt == self.hgt * self.wth
+    }
+
+    /// Recurrent configuration of the same class: the group element that
+    /// this configuration represents
+    pub fn recurrent(&self) -> Self {
+        self + &Self::zero(self.hgt, self.wth)
+    }
+
+    /// Inverse in the sandpile group: `c + c.inverse()` is the identity
+    pub fn inverse(&self) -> Self {
+        let zero = Self::zero(self.hgt, self.wth);
+        // `2 * zero` is larger than any stable configuration
+        let c = self.stabilize();
+        Self::from_fn(self.hgt, self.wth, |i, j| 2 * zero.get(i, j) - c.get(i, j)).recurrent()
+    }
+
+    /// `c + c + ... + c` (`k` times, at least once)
+    pub fn times(&self, k: usize) -> Self {
+        assert!(k > 0, "the empty sum is not defined for all configurations");
+        let mut acc = self.stabilize();
+        let mut pow = acc.clone();
+        let mut k = k - 1;
+        // double and add
+        while k > 0 {
+            if k % 2 == 1 {
+                acc = &acc + &pow;
+            }
+            pow = &pow + &pow;
+            k /= 2;
+        }
+        acc
+    }
+
+    /// Smallest `k` such that `k * c` is the identity, where `c` is the
+    /// group element of this configuration.
+    /// Only practical on small grids: the group itself is huge (it has
+    /// 557568000 elements on a 5x5 grid).
+    pub fn order(&self) -> usize {
+        let id = Self::identity(self.hgt, self.wth);
+        let c = self.recurrent();
+        let mut acc = c.clone();
+        let mut k = 1;
+        while acc != id {
+            acc = &acc + &c;
+            k += 1;
+        }
+        k
+    }
+
+    /// Write the configuration to a single image
+    pub fn save_image(&self, file: &str) {
+        Sandpile::from_config(self).save_image(file);
+    }
+
+    /// `hgt wth` on the first line, then one line of heights per row
+    fn to_text(&self) -> String {
+        format!("{} {}\n{}", self.hgt, self.wth, self)
+    }
+
+    /// Write as text (see `to_text`), to be read by `load` or
+    /// `Sandpile::load`
+    pub fn save(&self, file: &str) {
+        let mut f = BufWriter::new(File::create(file).unwrap());
+        write!(f, "{}", self.to_text()).unwrap();
+        f.flush().unwrap();
+    }
+
+    /// Read a file written by `save`, `#` starts a comment
+    pub fn load(file: &str) -> Result<Self, ParseError> {
+        Self::parse(&std::fs::read_to_string(file)?)
+    }
+
+    /// Same as `load`, from the contents of the file
+    pub fn parse(contents: &str) -> Result<Self, ParseError> {
+        let mut lines = significant_lines(contents);
+        let config = Self::parse_lines(&mut lines)?;
+        match lines.next() {
+            None => Ok(config),
+            Some((n, _)) => Err(ParseError::Invalid {
+                line: n,
+                msg: String::from("more lines than the height"),
+            }),
+        }
+    }
+
+    /// Read the header and the rows, leaving the lines that follow
+    fn parse_lines<'a, I>(lines: &mut I) -> Result<Self, ParseError>
+    where
+        I: Iterator<Item = (usize, &'a str)>,
+    {
+        let nums = |n: usize, line: &str| {
+            line.split_whitespace()
+                .map(|w| w.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ParseError::Invalid {
+                    line: n,
+                    msg: String::from("expected a non-negative number"),
+                })
+        };
+        let (n, header) = lines.next().ok_or(ParseError::Invalid {
+            line: 1,
+            msg: String::from("expected `hgt wth`"),
+        })?;
+        let (hgt, wth) = match nums(n, header)?[..] {
+            [hgt, wth] => (hgt, wth),
+            _ => {
+                return Err(ParseError::Invalid {
+                    line: n,
+                    msg: String::from("expected `hgt wth`"),
+                })
+            }
+        };
+        // rows are read one line at a time, so that a huge header does not
+        // allocate anything before the lines run out
+        hgt.checked_mul(wth).ok_or(ParseError::Invalid {
+            line: n,
+            msg: String::from("too many piles"),
+        })?;
+        let mut tab = Vec::new();
+        for i in 0..hgt {
+            let (n, line) = lines.next().ok_or(ParseError::Invalid {
+                line: n + i + 1,
+                msg: format!("expected {} rows", hgt),
+            })?;
+            let row = nums(n, line)?;
+            if row.len() != wth {
+                return Err(ParseError::Invalid {
+                    line: n,
+                    msg: format!("expected {} heights", wth),
+                });
+            }
+            tab.extend(row);
+        }
+        Ok(Self { hgt, wth, tab })
+    }
+}
+
+/// Non-empty lines with their number (starting at 1), without `#` comments
+fn significant_lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.split('#').next().unwrap_or("")))
+        .filter(|(_, line)| !line.trim().is_empty())
+}
+
+impl Add for &Configuration {
+    type Output = Configuration;
+
+    fn add(self, other: &Configuration) -> Configuration {
+        assert_eq!(
+            self.dims(),
+            other.dims(),
+            "cannot add configurations of different sizes"
+        );
+        Configuration::from_fn(self.hgt, self.wth, |i, j| self.get(i, j) + other.get(i, j))
+            .stabilize()
+    }
+}
+
+/// Group subtraction: `a - b = a + b.inverse()`
+impl Sub for &Configuration {
+    type Output = Configuration;
+
+    fn sub(self, other: &Configuration) -> Configuration {
+        Add::add(self, &other.inverse())
+    }
+}
+
+/// Pile by pile comparison: `a <= b` iff every pile of `a` has at most as
+/// many grains as the same pile of `b`
+impl PartialOrd for Configuration {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.dims() != other.dims() {
+            return None;
+        }
+        let pairs = || self.tab.iter().zip(other.tab.iter());
+        match (pairs().all(|(a, b)| a <= b), pairs().all(|(a, b)| a >= b)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+/// One line per row of piles
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.tab.chunks(self.wth.max(1)) {
+            let row: Vec<_> = row.iter().map(|h| h.to_string()).collect();
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grains added on each pile
+    type Drops<'a> = &'a [([usize; 2], usize)];
+
+    /// Stabilize one pile at a time
+    fn slow(hgt: usize, wth: usize, drops: Drops) -> Configuration {
+        let mut pile = Sandpile::new(hgt, wth);
+        for &([i, j], amount) in drops {
+            pile.add(i, j, amount);
+        }
+        pile.stabilize();
+        pile.config()
+    }
+
+    fn total(pile: &Sandpile) -> usize {
+        let config = pile.config();
+        let [hgt, wth] = config.dims();
+        (0..hgt * wth).map(|k| config.get(k / wth, k % wth)).sum()
+    }
+
+    #[test]
+    fn neighborhoods() {
+        let mut pile = Sandpile::with(3, 3, Neighborhood::Moore, Boundary::Sink);
+        pile.add(1, 1, 8);
+        pile.stabilize();
+        assert_eq!(pile.config(), config(&[&[1, 1, 1], &[1, 0, 1], &[1, 1, 1]]));
+        let mut pile = Sandpile::with(3, 3, Neighborhood::Hex, Boundary::Sink);
+        pile.add(1, 1, 6);
+        pile.stabilize();
+        assert_eq!(pile.config(), config(&[&[1, 1, 0], &[1, 0, 1], &[0, 1, 1]]));
+    }
+
+    #[test]
+    fn boundaries() {
+        // no grain is lost
+        let mut pile = Sandpile::with(3, 3, Neighborhood::VonNeumann, Boundary::Closed);
+        pile.add(0, 0, 20);
+        pile.stabilize();
+        assert_eq!(total(&pile), 20);
+        assert!(pile.config().is_stable());
+        // grains are only lost on the sink, which never holds any
+        let mut pile = Sandpile::with(4, 4, Neighborhood::Moore, Boundary::Torus([0, 0]));
+        pile.add(0, 0, 100);
+        assert_eq!(total(&pile), 0);
+        pile.add(2, 2, 100);
+        pile.stabilize();
+        assert_eq!(pile.config().get(0, 0), 0);
+        assert!((0..16).all(|k| pile.config().get(k / 4, k % 4) < 8));
+        // a threshold above the neighbor count loses the extra grains
+        let mut pile = Sandpile::with(3, 3, Neighborhood::VonNeumann, Boundary::Closed);
+        pile.set_threshold(5);
+        pile.add(1, 1, 5);
+        pile.stabilize();
+        assert_eq!(pile.config(), config(&[&[0, 1, 0], &[1, 0, 1], &[0, 1, 0]]));
+    }
+
+    #[test]
+    fn manna_below_neighbors() {
+        let mut pile = Sandpile::new(9, 9);
+        pile.set_model(Model::Manna, 42);
+        pile.set_threshold(2);
+        pile.add(4, 4, 50);
+        pile.stabilize();
+        let config = pile.config();
+        let mut total = 0;
+        for i in 0..9 {
+            for j in 0..9 {
+                assert!(config.get(i, j) < 2);
+                total += config.get(i, j);
+            }
+        }
+        // grains only fall off the border
+        assert!(total <= 50);
+    }
+
+    #[test]
+    #[should_panic]
+    fn btw_below_neighbors() {
+        Sandpile::new(3, 3).set_threshold(2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn directed_von_neumann() {
+        Sandpile::new(3, 3).set_model(Model::Directed, 0);
+    }
+
+    #[test]
+    fn directed_hex() {
+        let mut pile = Sandpile::with(4, 4, Neighborhood::Hex, Boundary::Sink);
+        pile.set_model(Model::Directed, 0);
+        pile.add(0, 1, 2);
+        pile.stabilize();
+        assert_eq!(pile.config().get(1, 1), 1);
+        assert_eq!(pile.config().get(1, 2), 1);
+    }
+
+    #[test]
+    fn load_checks_sizes() {
+        let file = std::env::temp_dir().join("sandpile_load_checks_sizes");
+        let file = file.to_str().unwrap();
+        let load = |data: &[u8]| {
+            std::fs::write(file, data).unwrap();
+            Sandpile::load(file)
+        };
+        // 2^35 x 2^35 piles, then a single height
+        let huge = b"SAND\x80\x80\x80\x80\x80\x01\x80\x80\x80\x80\x80\x01\x04\x00";
+        match load(huge) {
+            Err(ParseError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            other => panic!("{:?}", other.map(|p| p.dims())),
+        }
+        // 2 x 2 piles, threshold 4, pending pile [5, 0]
+        match load(b"SAND\x02\x02\x04\x00\x01\x02\x03\x01\x05\x00") {
+            Err(ParseError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            other => panic!("{:?}", other.map(|p| p.dims())),
+        }
+        match load(b"100000000000 100000000000\n1 2\n") {
+            Err(ParseError::Invalid { line: 1, .. }) => (),
+            other => panic!("{:?}", other.map(|p| p.dims())),
+        }
+        match load(b"100000 100000\n1 2\n") {
+            Err(ParseError::Invalid { line: 2, .. }) => (),
+            other => panic!("{:?}", other.map(|p| p.dims())),
+        }
+        match load(b"1 2\n4 0\npending 0 5\n") {
+            Err(ParseError::Invalid { line: 3, .. }) => (),
+            other => panic!("{:?}", other.map(|p| p.dims())),
+        }
+        let pile = load(b"SAND\x01\x02\x04\x05\x00\x01\x00\x00").unwrap();
+        assert_eq!(pile.dims(), [1, 2]);
+        assert_eq!(pile.config().get(0, 0), 5);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn save_load() {
+        let dir = std::env::temp_dir();
+        let mut moore = Sandpile::with(5, 6, Neighborhood::Moore, Boundary::Torus([1, 2]));
+        moore.add(2, 3, 20);
+        let mut manna = Sandpile::new(7, 7);
+        manna.set_model(Model::Manna, 9);
+        manna.set_threshold(2);
+        manna
+            .set_sources(Sources::parse("random 2 1\ndrip 3 3 1\npath 1 4 0 0 6 6", 5).unwrap())
+            .unwrap();
+        for _ in 0..10 {
+            manna.feed();
+            manna.stabilize();
+        }
+        manna.add(3, 3, 5);
+        let mut directed = Sandpile::with(4, 4, Neighborhood::Hex, Boundary::Sink);
+        directed.set_model(Model::Directed, 0);
+        let mut piles = [("moore", moore), ("manna", manna), ("directed", directed)];
+        for (name, pile) in piles.iter_mut() {
+            for ext in &["txt", "bin"] {
+                let file = dir.join(format!("sandpile_save_load_{}.{}", name, ext));
+                let file = file.to_str().unwrap();
+                pile.save(file).unwrap();
+                let mut loaded = Sandpile::load(file).unwrap();
+                std::fs::remove_file(file).unwrap();
+                assert_eq!(
+                    (loaded.neighborhood, loaded.boundary, loaded.model),
+                    (pile.neighborhood, pile.boundary, pile.model),
+                    "{}.{}",
+                    name,
+                    ext
+                );
+                assert_eq!(loaded.threshold, pile.threshold);
+                assert_eq!(loaded.schedule, pile.schedule);
+                assert_eq!(loaded.sources().sources(), pile.sources().sources());
+                // both go on in the same way, random choices included
+                for _ in 0..10 {
+                    for p in &mut [&mut *pile, &mut loaded] {
+                        p.stabilize();
+                        p.feed();
+                        p.add(1, 1, 3);
+                    }
+                    assert_eq!(loaded.config(), pile.config(), "{}.{}", name, ext);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_checks_rules() {
+        let file = std::env::temp_dir().join("sandpile_load_checks_rules.txt");
+        let file = file.to_str().unwrap();
+        let load = |text: &str| {
+            std::fs::write(file, text).unwrap();
+            Sandpile::load(file)
+        };
+        let pile = load("1 2\n0 1\nthreshold 2\nmodel manna\n").unwrap();
+        assert_eq!((pile.threshold, pile.model), (2, Model::Manna));
+        let pile = load("1 2\n0 1\nneighborhood hex\nmodel directed\n").unwrap();
+        assert_eq!(pile.threshold, 2);
+        let pile = load("1 2\n0 1\nboundary closed\n").unwrap();
+        assert_eq!(pile.boundary, Boundary::Closed);
+        for (text, line) in &[
+            ("1 2\n0 1\nthreshold 2\nmodel btw\n", 3),
+            ("1 2\n0 1\nthreshold 7\nneighborhood moore\n", 3),
+            ("1 2\n0 1\nmodel directed\n", 3),
+            ("1 2\n0 1\nmodel sandy\n", 3),
+            ("1 2\n0 1\nboundary torus 1 0\n", 3),
+            ("1 2\n0 1\nthreshold 0\nmodel manna\n", 3),
+            ("1 2\n0 1\nsource drip 0 2 1\n", 3),
+            ("1 2\n0 1\nsource pulse 0 0 1 0\n", 3),
+        ] {
+            match load(text) {
+                Err(ParseError::Invalid { line: l, .. }) => assert_eq!(l, *line, "{}", text),
+                other => panic!("{}: {:?}", text, other.map(|p| p.dims())),
+            }
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn sources_in_field() {
+        let mut pile = Sandpile::new(201, 201);
+        let sources = |text| Sources::parse(text, 0).unwrap();
+        assert!(pile.set_sources(sources("drip 500 500 5")).is_err());
+        assert!(pile.set_sources(sources("path 1 10 0 0 200 201")).is_err());
+        assert!(pile.set_sources(sources("pulse 0 201 1 2")).is_err());
+        assert!(pile.sources().is_empty());
+        pile.set_sources(sources("drip 200 200 5\nrandom 3 1\npath 1 10 0 0 200 200"))
+            .unwrap();
+        for _ in 0..50 {
+            pile.feed();
+        }
+    }
+
+    #[test]
+    fn steps() {
+        let mut pile = Sandpile::new(3, 3);
+        pile.add(1, 1, 4);
+        pile.add(0, 1, 3);
+        assert!(pile.step(Step::Topples(0)));
+        assert_eq!(pile.config().get(1, 1), 0);
+        assert_eq!(pile.config().get(0, 1), 4);
+        assert!(!pile.step(Step::Wave));
+        let mut steps = 1;
+        let mut pile = Sandpile::new(5, 5);
+        pile.add(2, 2, 40);
+        while pile.step(Step::Topples(0)) {
+            steps += 1;
+        }
+        assert_eq!(steps, pile.cnt);
+        // a large heap takes one wave per topple
+        let mut pile = Sandpile::new(3, 3);
+        pile.add(1, 1, 12);
+        assert!(pile.step(Step::Wave));
+        assert_eq!(pile.config().get(1, 1), 8);
+        assert_eq!(pile.config().get(0, 1), 1);
+        assert!(pile.step(Step::Wave));
+        assert_eq!(pile.config().get(1, 1), 4);
+        assert!(!pile.step(Step::Wave));
+        assert_eq!(pile.config().get(1, 1), 0);
+        assert_eq!(pile.config().get(0, 1), 3);
+        let mut stepped = Sandpile::new(9, 9);
+        stepped.add(4, 4, 300);
+        while stepped.step(Step::Wave) {}
+        let mut pile = Sandpile::new(9, 9);
+        pile.add(4, 4, 300);
+        pile.stabilize();
+        assert!(stepped.config() == pile.config());
+    }
+
+    #[test]
+    fn palette() {
+        let mut pile = Sandpile::new(1, 3);
+        pile.set_threshold(5);
+        pile.add(0, 0, 5);
+        pile.add(0, 2, 2);
+        let colors = |pile: &Sandpile| -> Vec<Color> {
+            let canvas = pile.canvas();
+            (0..3).map(|j| canvas[[0, j]].color()).collect()
+        };
+        assert_eq!(colors(&pile), vec![(0, 0, 0), (0, 0, 0), shade(2, 5)]);
+        pile.set_palette(Palette::AVALANCHE);
+        assert_eq!(colors(&pile)[0], heat(5, 5));
+        pile.stabilize();
+        assert_eq!(colors(&pile)[0], (25, 25, 25));
+        assert_eq!(colors(&pile)[1], shade(1, 5));
+    }
+
+    fn config(rows: &[&[usize]]) -> Configuration {
+        Configuration::from_fn(rows.len(), rows[0].len(), |i, j| rows[i][j])
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(Configuration::identity(1, 1), config(&[&[0]]));
+        assert_eq!(Configuration::identity(2, 2), config(&[&[2, 2], &[2, 2]]));
+        assert_eq!(
+            Configuration::identity(3, 3),
+            config(&[&[2, 1, 2], &[1, 0, 1], &[2, 1, 2]])
+        );
+        for &(hgt, wth) in &[(2, 3), (4, 4), (5, 7)] {
+            let e = Configuration::identity(hgt, wth);
+            assert!(e.is_stable() && e.is_recurrent());
+            assert_eq!(&e + &e, e);
+            let m = Configuration::max_stable(hgt, wth);
+            assert_eq!(&e + &m, m);
+        }
+    }
+
+    #[test]
+    fn addition() {
+        let a = config(&[&[3, 3], &[3, 3]]);
+        let b = config(&[&[1, 0], &[0, 0]]);
+        assert_eq!(&a + &b, config(&[&[2, 1], &[1, 1]]));
+        assert!(!Configuration::filled(2, 2, 4).is_stable());
+        assert_eq!(
+            Configuration::filled(2, 2, 4).stabilize(),
+            config(&[&[2, 2], &[2, 2]])
+        );
+    }
+
+    /// All stable configurations of a small grid
+    fn stable(hgt: usize, wth: usize) -> impl Iterator<Item = Configuration> {
+        let n = hgt * wth;
+        (0..1usize << (2 * n))
+            .map(move |x| Configuration::from_fn(hgt, wth, |i, j| (x >> (2 * (i * wth + j))) & 3))
+    }
+
+    #[test]
+    fn burning() {
+        // number of spanning trees of the grid with a sink vertex
+        assert_eq!(
+            stable(2, 2).filter(Configuration::is_recurrent).count(),
+            192
+        );
+        assert_eq!(stable(1, 3).filter(Configuration::is_recurrent).count(), 56);
+        assert_eq!(
+            stable(3, 3).filter(Configuration::is_recurrent).count(),
+            100352
+        );
+        assert!(!config(&[&[0, 0], &[3, 3]]).is_recurrent());
+        assert!(config(&[&[0, 3], &[3, 3]]).is_recurrent());
+        assert!(!Configuration::filled(2, 2, 4).is_recurrent());
+    }
+
+    #[test]
+    fn group() {
+        let e = Configuration::identity(3, 4);
+        let a = Configuration::from_fn(3, 4, |i, j| (i * 7 + j * 3) % 4);
+        let b = Configuration::from_fn(3, 4, |i, j| (i + j) % 3);
+        assert!(a.recurrent().is_recurrent());
+        assert_eq!(&a + &a.inverse(), e);
+        assert_eq!(&(&a - &b) + &b, a.recurrent());
+        assert_eq!(&a + &b, &b + &a);
+        assert_eq!(a.times(1), a.stabilize());
+        assert_eq!(a.times(3), &(&a + &a) + &a);
+        let m = Configuration::max_stable(2, 2);
+        let k = m.order();
+        assert_eq!(192 % k, 0);
+        assert_eq!(m.times(k).recurrent(), Configuration::identity(2, 2));
+        assert_eq!(Configuration::identity(2, 2).order(), 1);
+    }
+
+    #[test]
+    fn ordering() {
+        let a = config(&[&[1, 2], &[3, 0]]);
+        assert!(a <= Configuration::max_stable(2, 2));
+        assert!(Configuration::filled(2, 2, 0) < a);
+        assert_eq!(a.partial_cmp(&config(&[&[2, 1], &[3, 0]])), None);
+        assert_eq!(a.partial_cmp(&Configuration::filled(1, 4, 0)), None);
+    }
+
+    #[test]
+    fn text() {
+        let a = config(&[&[1, 2, 3], &[10, 0, 4]]);
+        assert_eq!(a.to_string(), "1 2 3\n10 0 4\n");
+        assert_eq!(Configuration::parse(&a.to_text()).unwrap(), a);
+        let commented = "# comment\n2 3\n1 2 3 # first row\n\n10 0 4\n";
+        assert_eq!(Configuration::parse(commented).unwrap(), a);
+        match Configuration::parse("2 3\n1 2 3\n10 0\n") {
+            Err(ParseError::Invalid { line: 3, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn bulk_matches_stabilize() {
+        let cases: &[(usize, usize, Drops)] = &[
+            (5, 5, &[([2, 2], 100)]),
+            (4, 7, &[([0, 0], 37), ([3, 6], 51), ([1, 4], 9)]),
+            (1, 6, &[([0, 2], 25)]),
+            (6, 3, &[([5, 1], 1000), ([2, 2], 3)]),
+        ];
+        for &(hgt, wth, drops) in cases {
+            let mut pile = Sandpile::new(hgt, wth);
+            for &([i, j], amount) in drops {
+                pile.add(i, j, amount);
+            }
+            pile.stabilize_bulk();
+            assert_eq!(pile.config(), slow(hgt, wth, drops));
+        }
+    }
+
+    #[test]
+    fn single_source_matches_stabilize() {
+        // above `MULTISCALE`, from the odometer guessed with a quarter of the
+        // grains
+        for &grains in &[0, 1, 4, 5, 17, 64, 300, 1000, 4321, 5000, 17000] {
+            let pile = Sandpile::single_source(grains);
+            let [hgt, wth] = pile.dims();
+            assert_eq!(hgt % 2, 1);
+            let expected = slow(hgt, wth, &[([hgt / 2, wth / 2], grains)]);
+            assert_eq!(pile.config(), expected, "{} grains", grains);
+            // large enough that no grain was lost
+            let total: usize = (0..hgt)
+                .flat_map(|i| (0..wth).map(move |j| (i, j)))
+                .map(|(i, j)| expected.get(i, j))
+                .sum();
+            assert_eq!(total, grains);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::io;
+use std::process::Command;
+
+use crate::export::Format;
+use crate::lifelike::*;
+use crate::pattern::*;
+
+/// RGB color, each component in the range [0, 255]
+pub type Rgb = [u8; 3];
+
+/// A screenshot
+pub struct Image {
+    hgt: usize,
+    wth: usize,
+    px: Vec<Rgb>,
+}
+
+/// Split the header of a PPM file into `n` tokens, skipping `#` comments.
+/// Returns the tokens and the position right after the last one.
+fn ppm_tokens(data: &[u8], n: usize) -> Option<(Vec<String>, usize)> {
+    let mut tokens = Vec::new();
+    let mut k = 0;
+    while tokens.len() < n {
+        while k < data.len() && data[k].is_ascii_whitespace() {
+            k += 1;
+        }
+        if k < data.len() && data[k] == b'#' {
+            while k < data.len() && data[k] != b'\n' {
+                k += 1;
+            }
+            continue;
+        }
+        let start = k;
+        while k < data.len() && !data[k].is_ascii_whitespace() {
+            k += 1;
+        }
+        if start == k {
+            return None;
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..k]).into_owned());
+    }
+    Some((tokens, k))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Image {
+    /// Read an image: `*.ppm` files are read directly, anything else (PNG,
+    /// JPEG, ...) is converted by `ffmpeg` first
+    pub fn open(file: &str) -> io::Result<Self> {
+        match file.split('.').next_back() {
+            Some("ppm") | Some("pnm") => Self::from_ppm(&std::fs::read(file)?),
+            _ => {
+                let out = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-i", file])
+                    .args(["-f", "image2pipe", "-vcodec", "ppm", "-"])
+                    .output()?;
+                if !out.status.success() {
+                    return Err(invalid(&String::from_utf8_lossy(&out.stderr)));
+                }
+                Self::from_ppm(&out.stdout)
+            }
+        }
+    }
+
+    /// Decode a binary (`P6`) or ASCII (`P3`) PPM image
+    pub fn from_ppm(data: &[u8]) -> io::Result<Self> {
+        let (header, end) = ppm_tokens(data, 4).ok_or_else(|| invalid("truncated PPM header"))?;
+        let num = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| invalid("invalid number in PPM header"))
+        };
+        let (wth, hgt, max) = (num(&header[1])?, num(&header[2])?, num(&header[3])?);
+        if max == 0 || max > 65535 {
+            return Err(invalid("invalid PPM maximum value"));
+        }
+        let scale = |v: usize| (v * 255 / max) as u8;
+        // the number of values can not be larger than the file, which keeps
+        // a crafted header from overflowing
+        let depth = if max < 256 { 1 } else { 2 };
+        let size = wth
+            .checked_mul(hgt)
+            .and_then(|size| size.checked_mul(3))
+            .filter(|&n| n.checked_mul(depth).is_some_and(|n| n <= data.len()))
+            .ok_or_else(|| invalid("truncated PPM data"))?;
+        let values: Vec<usize> = match header[0].as_str() {
+            "P3" => {
+                let (v, _) =
+                    ppm_tokens(&data[end..], size).ok_or_else(|| invalid("truncated PPM data"))?;
+                let v = v.iter().map(|s| num(s)).collect::<io::Result<Vec<_>>>()?;
+                if v.iter().any(|&x| x > max) {
+                    return Err(invalid("PPM value larger than the maximum"));
+                }
+                v
+            }
+            "P6" => {
+                // exactly one whitespace separates the header from the data
+                let body = &data[(end + 1).min(data.len())..];
+                if body.len() < size * depth {
+                    return Err(invalid("truncated PPM data"));
+                }
+                body.chunks(depth)
+                    .take(size)
+                    .map(|c| c.iter().fold(0, |acc, &b| acc * 256 + b as usize))
+                    .collect()
+            }
+            _ => return Err(invalid("not a PPM image")),
+        };
+        let px = values
+            .chunks(3)
+            .map(|c| [scale(c[0]), scale(c[1]), scale(c[2])])
+            .collect();
+        Ok(Self { hgt, wth, px })
+    }
+
+    fn get(&self, i: usize, j: usize) -> Rgb {
+        self.px[i * self.wth + j]
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> usize {
+    (0..3)
+        .map(|k| (a[k] as isize - b[k] as isize).pow(2) as usize)
+        .sum()
+}
+
+/// How to find the cells on the image
+pub enum Grid {
+    /// Detect the size of the cells and their alignment from the image
+    Auto,
+    /// Centers `[y, x]` of the upper left and lower right cells, and number of
+    /// cells `[hgt, wth]` between them (inclusive), as in `lifescan.py`
+    Corners([usize; 2], [usize; 2], [usize; 2]),
+}
+
+/// Settings of a scan
+pub struct Scan {
+    pub grid: Grid,
+    /// Colors of dead and live cells, detected if not given
+    pub colors: Option<[Rgb; 2]>,
+}
+
+impl Scan {
+    /// Turn the image into a pattern
+    pub fn run(&self, img: &Image) -> Pattern {
+        let [dead, live] = self.colors.unwrap_or_else(|| detect_colors(img));
+        let alive =
+            |i: usize, j: usize| distance(img.get(i, j), live) < distance(img.get(i, j), dead);
+        let (centers_i, centers_j, radius) = match self.grid {
+            Grid::Corners([i1, j1], [i2, j2], [hgt, wth]) => {
+                let spread = |a: usize, b: usize, n: usize| -> Vec<usize> {
+                    (0..n)
+                        .map(|k| {
+                            if n == 1 {
+                                a
+                            } else {
+                                (a as isize
+                                    + (b as isize - a as isize) * k as isize / (n - 1) as isize)
+                                    as usize
+                            }
+                        })
+                        .collect()
+                };
+                let pitch = ((i2 as f64 - i1 as f64).abs() / hgt.max(2) as f64)
+                    .min((j2 as f64 - j1 as f64).abs() / wth.max(2) as f64);
+                (
+                    spread(i1, i2, hgt),
+                    spread(j1, j2, wth),
+                    (pitch / 4.) as usize,
+                )
+            }
+            Grid::Auto => {
+                let mask: Vec<bool> = (0..img.hgt * img.wth)
+                    .map(|k| alive(k / img.wth, k % img.wth))
+                    .collect();
+                let (pitch, [oi, oj]) = detect_grid(&mask, img.hgt, img.wth);
+                let centers = |o: usize, n: usize| {
+                    (0..)
+                        .map(|k| o + k * pitch)
+                        .take_while(|&c| c < n)
+                        .collect()
+                };
+                (centers(oi, img.hgt), centers(oj, img.wth), pitch / 4)
+            }
+        };
+        let mut cells = Vec::new();
+        for (ci, &i) in centers_i.iter().enumerate() {
+            for (cj, &j) in centers_j.iter().enumerate() {
+                // majority vote around the center to be robust to noise
+                let (mut yes, mut total) = (0, 0);
+                for si in i.saturating_sub(radius)..=(i + radius).min(img.hgt - 1) {
+                    for sj in j.saturating_sub(radius)..=(j + radius).min(img.wth - 1) {
+                        total += 1;
+                        if alive(si, sj) {
+                            yes += 1;
+                        }
+                    }
+                }
+                if 2 * yes > total {
+                    cells.push([ci as isize, cj as isize]);
+                }
+            }
+        }
+        Pattern::from_cells(cells).normalize()
+    }
+}
+
+/// The most common color is the background (dead cells), live cells are
+/// the most distant color among the few most common ones (grid lines tend
+/// to be closer to the background).
+/// An empty image is assumed to be white on black.
+fn detect_colors(img: &Image) -> [Rgb; 2] {
+    let mut hist: HashMap<Rgb, usize> = HashMap::new();
+    for &p in &img.px {
+        *hist.entry(p).or_insert(0) += 1;
+    }
+    let mut freq: Vec<_> = hist.into_iter().collect();
+    freq.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let dead = match freq.first() {
+        Some(&(c, _)) => c,
+        None => return [[0, 0, 0], [255, 255, 255]],
+    };
+    let live = freq
+        .iter()
+        .take(4)
+        .map(|&(c, _)| c)
+        .max_by_key(|&c| distance(c, dead))
+        .unwrap();
+    [dead, live]
+}
+
+/// Find the size of the cells and the position `[i, j]` of the center of
+/// the first cell.
+///
+/// A run of live pixels covers a whole number of cells, minus the width of
+/// the grid lines if there are any, so the shortest run gives a lower bound
+/// on the size of the cells. Above it, the right size is the one for which
+/// runs start (and end) at the same position modulo the size.
+fn detect_grid(mask: &[bool], hgt: usize, wth: usize) -> (usize, [usize; 2]) {
+    // starts and ends of the runs of live pixels, for lines then columns
+    let mut rising = [Vec::new(), Vec::new()];
+    let mut falling = [Vec::new(), Vec::new()];
+    let mut shortest = usize::MAX;
+    for (dir, &(outer, inner)) in [(wth, hgt), (hgt, wth)].iter().enumerate() {
+        for a in 0..outer {
+            let at = |b: usize| {
+                if dir == 0 {
+                    mask[b * wth + a]
+                } else {
+                    mask[a * wth + b]
+                }
+            };
+            let mut b = 0;
+            while b < inner {
+                if !at(b) {
+                    b += 1;
+                    continue;
+                }
+                let start = b;
+                while b < inner && at(b) {
+                    b += 1;
+                }
+                // runs cut by the border of the image do not have a meaningful length
+                if start > 0 && b < inner {
+                    rising[dir].push(start);
+                    falling[dir].push(b);
+                    shortest = shortest.min(b - start);
+                }
+            }
+        }
+    }
+    if shortest == usize::MAX {
+        return (hgt.max(wth).max(1), [0, 0]);
+    }
+    // fraction of the positions that fall on the most common value modulo `p`
+    let best_bin = |v: &[usize], p: usize| {
+        let mut bins = vec![0; p];
+        for &x in v {
+            bins[x % p] += 1;
+        }
+        (0..p)
+            .max_by_key(|&k| (bins[k], p - k))
+            .map(|k| (k, bins[k]))
+    };
+    let score = |p: usize| -> usize {
+        rising
+            .iter()
+            .chain(falling.iter())
+            .filter_map(|v| best_bin(v, p))
+            .map(|(_, n)| n)
+            .sum()
+    };
+    // grid lines are assumed to be thinner than half a cell
+    let pitch = (shortest..=shortest + shortest / 2 + 1)
+        .max_by_key(|&p| (score(p), usize::MAX - p))
+        .unwrap();
+    let center = |dir: usize| {
+        let start = best_bin(&rising[dir], pitch).map_or(0, |(k, _)| k);
+        let end = best_bin(&falling[dir], pitch).map_or(0, |(k, _)| k);
+        let len = (end + pitch - start - 1) % pitch + 1;
+        (start + len / 2) % pitch
+    };
+    (pitch, [center(0), center(1)])
+}
+
+/// `scan <image> <output> [--corners y1 x1 y2 x2 hgt wth] [--colors r,g,b r,g,b]`
+///
+/// Read a screenshot and write the pattern it shows to `output`, in the
+/// format given by its extension (see `LifeLike::save_to_file`).
+pub fn main(args: &[String]) {
+    let usage =
+        "usage: scan <image> <output> [--corners y1 x1 y2 x2 hgt wth] [--colors r,g,b r,g,b]";
+    let scan = parse_args(args).unwrap_or_else(|| {
+        eprintln!("{}", usage);
+        std::process::exit(2)
+    });
+    let img = Image::open(&args[0]).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", args[0], e);
+        std::process::exit(1)
+    });
+    let pat = scan.run(&img);
+    let [hgt, wth] = pat.bounding_box().map_or([1, 1], |(_, size)| size);
+    let mut game = LifeLike::new(hgt, wth, LIFE);
+    pat.stamp(&mut game, 0, 0, &T_NONE, Anchor::TopLeft);
+    game.save_to_file(&args[1]).unwrap_or_else(|e| {
+        eprintln!("could not write {}: {}", args[1], e);
+        std::process::exit(1)
+    });
+    eprintln!("{} live cells written to {}", pat.len(), args[1]);
+}
+
+/// Settings given on the command line, `None` if they do not follow the
+/// usage or if the output is not a pattern file that can be written
+fn parse_args(args: &[String]) -> Option<Scan> {
+    if args.len() < 2 {
+        return None;
+    }
+    match Format::from_file(&args[1]) {
+        None | Some(Format::Macrocell) => return None,
+        Some(_) => (),
+    }
+    let mut scan = Scan {
+        grid: Grid::Auto,
+        colors: None,
+    };
+    let color = |s: &String| -> Option<Rgb> {
+        let v = s
+            .split(',')
+            .map(|c| c.parse().ok())
+            .collect::<Option<Vec<u8>>>()?;
+        match v[..] {
+            [r, g, b] => Some([r, g, b]),
+            _ => None,
+        }
+    };
+    let mut k = 2;
+    while k < args.len() {
+        match args[k].as_str() {
+            "--corners" if k + 6 < args.len() => {
+                let v = args[k + 1..k + 7]
+                    .iter()
+                    .map(|s| s.parse::<usize>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                scan.grid = Grid::Corners([v[0], v[1]], [v[2], v[3]], [v[4], v[5]]);
+                k += 7;
+            }
+            "--colors" if k + 2 < args.len() => {
+                scan.colors = Some([color(&args[k + 1])?, color(&args[k + 2])?]);
+                k += 3;
+            }
+            _ => return None,
+        }
+    }
+    Some(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Binary PPM of a pattern, cells of `pitch` pixels separated by grid
+    /// lines of 1 pixel
+    fn screenshot(pat: &Pattern, pitch: usize) -> Vec<u8> {
+        let ([i0, j0], [hgt, wth]) = pat.bounding_box().unwrap();
+        // a margin of one dead cell all around
+        let (hgt, wth) = ((hgt + 2) * pitch, (wth + 2) * pitch);
+        let mut data = format!("P6\n# synthetic\n{} {}\n255\n", wth, hgt).into_bytes();
+        for i in 0..hgt {
+            for j in 0..wth {
+                let cell = [(i / pitch) as isize - 1 + i0, (j / pitch) as isize - 1 + j0];
+                let px = if i % pitch == 0 || j % pitch == 0 {
+                    [60, 60, 60]
+                } else if pat.contains(cell[0], cell[1]) {
+                    [255, 255, 255]
+                } else {
+                    [0, 0, 0]
+                };
+                data.extend(&px);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn ppm() {
+        let img = Image::from_ppm(b"P3 2 1 # two pixels\n 4\n0 0 0 4 2 4\n").unwrap();
+        assert_eq!((img.hgt, img.wth), (1, 2));
+        assert_eq!(img.px, vec![[0, 0, 0], [255, 127, 255]]);
+        let img = Image::from_ppm(b"P6\n1 1\n65535\n\xff\xff\x00\x00\x80\x00").unwrap();
+        assert_eq!(img.px, vec![[255, 0, 127]]);
+        assert!(Image::from_ppm(b"P6 2 2 255\n\x00\x00\x00").is_err());
+        assert!(Image::from_ppm(b"P5 1 1 255\n\x00").is_err());
+        // sizes that overflow, values above the maximum
+        let huge = b"P6 4294967296 4294967296 255\n\x00\x00\x00";
+        assert!(Image::from_ppm(huge).is_err());
+        assert!(Image::from_ppm(b"P3 6148914691236517206 1 255\n0 0 0").is_err());
+        assert!(Image::from_ppm(b"P3 1 1 255\n0 0 256").is_err());
+        // an empty image is valid and does not have any cell
+        let empty = Image::from_ppm(b"P3 0 0 255\n").unwrap();
+        assert_eq!(detect_colors(&empty), [[0, 0, 0], [255, 255, 255]]);
+        let scan = Scan {
+            grid: Grid::Auto,
+            colors: None,
+        };
+        assert_eq!(scan.run(&empty).len(), 0);
+    }
+
+    #[test]
+    fn grid() {
+        let glider = Pattern::from_apgcode("xq4_153").unwrap();
+        let img = Image::from_ppm(&screenshot(&glider, 6)).unwrap();
+        let [dead, live] = detect_colors(&img);
+        assert_eq!((dead, live), ([0, 0, 0], [255, 255, 255]));
+        let mask: Vec<_> = img.px.iter().map(|&p| p == live).collect();
+        let (pitch, [oi, oj]) = detect_grid(&mask, img.hgt, img.wth);
+        assert_eq!(pitch, 6);
+        // centers of the cells, between the grid lines
+        assert!((2..=4).contains(&oi) && (2..=4).contains(&oj));
+        let scan = Scan {
+            grid: Grid::Auto,
+            colors: None,
+        };
+        assert_eq!(scan.run(&img), glider.normalize());
+    }
+
+    #[test]
+    fn args() {
+        let args = |s: &str| -> Vec<String> { s.split_whitespace().map(String::from).collect() };
+        assert!(parse_args(&args("in.png")).is_none());
+        assert!(parse_args(&args("in.png out.png")).is_none());
+        assert!(parse_args(&args("in.png out.mc")).is_none());
+        assert!(parse_args(&args("in.png out.rle --corners 1 2 3")).is_none());
+        assert!(parse_args(&args("in.png out.rle --colors 0,0,0 1,2")).is_none());
+        assert!(parse_args(&args("in.png out.rle --colors 0,0,0 1,2,300")).is_none());
+        let scan = parse_args(&args("in.png out.rle --colors 0,0,0 1,2,3")).unwrap();
+        assert_eq!(scan.colors, Some([[0, 0, 0], [1, 2, 3]]));
+        let scan = parse_args(&args("in.png out.rle --corners 1 2 3 4 5 6")).unwrap();
+        assert!(matches!(scan.grid, Grid::Corners([1, 2], [3, 4], [5, 6])));
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt;
+use std::io;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::parse::ParseError;
+
+/// Random generator started from `seed`, at `pos` words into its stream.
+///
+/// The position of a fresh generator cannot be read, so all generators that
+/// may be saved (see `Sandpile::save`) are made with this function.
+pub fn generator(seed: u64, pos: u128) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_word_pos(pos);
+    rng
+}
+
+/// A way of adding grains to a sandpile, once per frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// `amount` grains on `pos` every frame
+    Drip { pos: [usize; 2], amount: usize },
+    /// `amount` grains on each of `count` piles chosen at random every frame
+    Random { count: usize, amount: usize },
+    /// `amount` grains on `pos` every `period` frames, starting at frame
+    /// `phase`
+    Pulse {
+        pos: [usize; 2],
+        amount: usize,
+        period: usize,
+        phase: usize,
+    },
+    /// `amount` grains every frame on a point that moves in straight lines
+    /// from one of the `points` to the next, `frames` frames per segment.
+    /// The point starts over from the first one after reaching the last.
+    Path {
+        points: Vec<[usize; 2]>,
+        amount: usize,
+        frames: usize,
+    },
+}
+
+impl Source {
+    /// Fixed positions that the source uses (the path between two points
+    /// stays in their bounding box)
+    pub fn positions(&self) -> &[[usize; 2]] {
+        match self {
+            Source::Drip { pos, .. } | Source::Pulse { pos, .. } => std::slice::from_ref(pos),
+            Source::Random { .. } => &[],
+            Source::Path { points, .. } => points,
+        }
+    }
+
+    /// Whether the source can feed a sandpile: pulses need a period and
+    /// paths need points and some frames per segment
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Source::Pulse { period: 0, .. } => Err(String::from("pulse period must be positive")),
+            Source::Path { points, .. } if points.is_empty() => {
+                Err(String::from("path needs at least one point"))
+            }
+            Source::Path { frames: 0, .. } => Err(String::from("path frames must be positive")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Piles that receive grains at frame `t` in a `hgt x wth` sandpile
+    fn drops(
+        &self,
+        t: usize,
+        hgt: usize,
+        wth: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<([usize; 2], usize)> {
+        match self {
+            Source::Drip { pos, amount } => vec![(*pos, *amount)],
+            // no pile to choose from
+            Source::Random { .. } if hgt == 0 || wth == 0 => Vec::new(),
+            Source::Random { count, amount } => (0..*count)
+                .map(|_| ([rng.gen_range(0, hgt), rng.gen_range(0, wth)], *amount))
+                .collect(),
+            Source::Pulse {
+                pos,
+                amount,
+                period,
+                phase,
+            } => match t.checked_sub(*phase).map(|dt| dt % period) {
+                Some(0) => vec![(*pos, *amount)],
+                _ => Vec::new(),
+            },
+            Source::Path {
+                points,
+                amount,
+                frames,
+            } => {
+                if points.len() == 1 {
+                    return vec![(points[0], *amount)];
+                }
+                let seg = (t / frames) % (points.len() - 1);
+                let k = (t % frames) as isize;
+                let (a, b) = (points[seg], points[seg + 1]);
+                let lerp = |x: usize, y: usize| {
+                    (x as isize + (y as isize - x as isize) * k / *frames as isize) as usize
+                };
+                vec![([lerp(a[0], b[0]), lerp(a[1], b[1])], *amount)]
+            }
+        }
+    }
+}
+
+/// Same format as a line of `Sources::parse`
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Drip { pos, amount } => write!(f, "drip {} {} {}", pos[0], pos[1], amount),
+            Source::Random { count, amount } => write!(f, "random {} {}", count, amount),
+            Source::Pulse {
+                pos,
+                amount,
+                period,
+                phase,
+            } => write!(
+                f,
+                "pulse {} {} {} {} {}",
+                pos[0], pos[1], amount, period, phase
+            ),
+            Source::Path {
+                points,
+                amount,
+                frames,
+            } => {
+                write!(f, "path {} {}", amount, frames)?;
+                for p in points {
+                    write!(f, " {} {}", p[0], p[1])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The list of sources that feed a sandpile, and the current frame.
+///
+/// Text format, one source per line, `#` starts a comment:
+/// ```txt
+/// drip i j amount
+/// random count amount
+/// pulse i j amount period [phase]
+/// path amount frames i1 j1 i2 j2 ...
+/// ```
+pub struct Sources {
+    sources: Vec<Source>,
+    tick: usize,
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl Sources {
+    /// No sources yet, `seed` initializes the random drops
+    pub fn new(seed: u64) -> Self {
+        Self {
+            sources: Vec::new(),
+            tick: 0,
+            seed,
+            rng: generator(seed, 0),
+        }
+    }
+
+    /// Add a source, if it passes `Source::check`
+    pub fn with(mut self, source: Source) -> Result<Self, ParseError> {
+        source
+            .check()
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+        self.sources.push(source);
+        Ok(self)
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Number of frames fed so far
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// Number of frames fed so far, seed and position in the stream of
+    /// random drops, to go on later with `resume`
+    pub fn state(&self) -> (usize, u64, u128) {
+        (self.tick, self.seed, self.rng.get_word_pos())
+    }
+
+    /// Go on from a state returned by `state` (the seed is the one given
+    /// to `new`)
+    pub fn resume(mut self, tick: usize, pos: u128) -> Self {
+        self.tick = tick;
+        self.rng = generator(self.seed, pos);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Read sources from a file (see the text format above)
+    pub fn from_file(file: &str, seed: u64) -> Result<Self, ParseError> {
+        Self::parse(&std::fs::read_to_string(file)?, seed)
+    }
+
+    /// Same as `from_file`, from the contents of the file
+    pub fn parse(contents: &str, seed: u64) -> Result<Self, ParseError> {
+        let mut s = Self::new(seed);
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = |msg: &str| ParseError::Invalid {
+                line: n + 1,
+                msg: String::from(msg),
+            };
+            let nums = words
+                .iter()
+                .skip(1)
+                .map(|w| w.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("expected a non-negative number"))?;
+            let source = match (words.first(), &nums[..]) {
+                (None, _) => continue,
+                (Some(&"drip"), &[i, j, amount]) => Source::Drip {
+                    pos: [i, j],
+                    amount,
+                },
+                (Some(&"random"), &[count, amount]) => Source::Random { count, amount },
+                (Some(&"pulse"), &[i, j, amount, period]) => Source::Pulse {
+                    pos: [i, j],
+                    amount,
+                    period,
+                    phase: 0,
+                },
+                (Some(&"pulse"), &[i, j, amount, period, phase]) => Source::Pulse {
+                    pos: [i, j],
+                    amount,
+                    period,
+                    phase,
+                },
+                (Some(&"path"), &[amount, frames, ref pts @ ..]) if pts.len() % 2 == 0 => {
+                    Source::Path {
+                        points: pts.chunks(2).map(|p| [p[0], p[1]]).collect(),
+                        amount,
+                        frames,
+                    }
+                }
+                (Some(&"drip"), _) => return Err(invalid("expected `drip i j amount`")),
+                (Some(&"random"), _) => return Err(invalid("expected `random count amount`")),
+                (Some(&"pulse"), _) => {
+                    return Err(invalid("expected `pulse i j amount period [phase]`"))
+                }
+                (Some(&"path"), _) => {
+                    return Err(invalid("expected `path amount frames i1 j1 i2 j2 ...`"))
+                }
+                (Some(_), _) => {
+                    return Err(invalid("expected `drip`, `random`, `pulse` or `path`"))
+                }
+            };
+            source.check().map_err(|msg| invalid(&msg))?;
+            s.sources.push(source);
+        }
+        Ok(s)
+    }
+
+    /// Grains to add for the current frame in a `hgt x wth` sandpile, then
+    /// go to the next frame
+    pub fn next(&mut self, hgt: usize, wth: usize) -> Vec<([usize; 2], usize)> {
+        let t = self.tick;
+        self.tick += 1;
+        let rng = &mut self.rng;
+        self.sources
+            .iter()
+            .flat_map(|s| s.drops(t, hgt, wth, rng))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked() {
+        let pulse = Source::Pulse {
+            pos: [0, 0],
+            amount: 1,
+            period: 0,
+            phase: 0,
+        };
+        let empty = Source::Path {
+            points: Vec::new(),
+            amount: 1,
+            frames: 10,
+        };
+        let still = Source::Path {
+            points: vec![[0, 0], [1, 1]],
+            amount: 1,
+            frames: 0,
+        };
+        for source in [pulse, empty, still].iter().cloned() {
+            assert!(source.check().is_err());
+            assert!(Sources::new(0).with(source).is_err());
+        }
+        for text in &["pulse 0 0 1 0", "path 1 0 0 0 1 1", "path 1 10"] {
+            match Sources::parse(text, 0) {
+                Err(ParseError::Invalid { line: 1, .. }) => (),
+                other => panic!("{}: {:?}", text, other.map(|s| s.sources)),
+            }
+        }
+        let drip = Source::Drip {
+            pos: [1, 2],
+            amount: 3,
+        };
+        let sources = Sources::new(0).with(drip.clone()).unwrap();
+        assert_eq!(sources.sources(), &[drip]);
+    }
+
+    #[test]
+    fn resumed() {
+        let text = "random 3 1\npulse 1 2 3 4 1\npath 1 5 0 0 4 4 0 8";
+        let mut sources = Sources::parse(text, 7).unwrap();
+        let lines: Vec<_> = sources.sources().iter().map(Source::to_string).collect();
+        assert_eq!(lines.join("\n"), text);
+        for _ in 0..10 {
+            sources.next(20, 20);
+        }
+        let (tick, seed, pos) = sources.state();
+        assert_eq!((tick, seed), (10, 7));
+        let mut resumed = Sources::parse(&lines.join("\n"), seed)
+            .unwrap()
+            .resume(tick, pos);
+        for _ in 0..10 {
+            assert_eq!(resumed.next(20, 20), sources.next(20, 20));
+        }
+    }
+
+    #[test]
+    fn random_on_empty_field() {
+        let mut sources = Sources::parse("random 5 1", 0).unwrap();
+        assert!(sources.next(0, 10).is_empty());
+        assert_eq!(sources.next(10, 10).len(), 5);
+    }
+}
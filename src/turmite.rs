@@ -1,17 +1,33 @@
 use crate::canvas::*;
+use crate::parse::ParseError;
 use rand::Rng;
 
 /// Trace left by the turmites
 type Mark = usize;
 
-/// Change orientation
-#[derive(Clone, Copy, Eq, PartialEq)]
+/// Internal state of a turmite
+type State = usize;
+
+/// Change orientation (the names of the usual turmite notation).
+/// `Left` and `Right` go to the next direction: 90 degrees on a square
+/// grid, 60 degrees on a hexagonal or triangular one.
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Turn {
     Left,
     Right,
+    /// Keep going in the same direction
+    NoTurn,
+    /// Go back
+    UTurn,
+    /// Two directions to the left (120 degrees on a hexagonal grid, same as
+    /// `UTurn` on a square one)
+    Left2,
+    /// Two directions to the right
+    Right2,
 }
 
-/// Orientation of a turmite
+/// Orientation of a turmite on a square grid
 #[derive(Clone, Copy)]
 pub enum Dir {
     N,
@@ -20,8 +36,86 @@ pub enum Dir {
     E,
 }
 
-/// Transition table for how to move the turmite
-pub type Rules<'a> = &'a [&'a [(Mark, Turn)]];
+/// Shape of the cells that turmites walk on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lattice {
+    /// 4 directions: N, E, S, W
+    Square,
+    /// 6 directions, clockwise from E. Odd lines are shifted to the right
+    /// by half a cell.
+    Hex,
+    /// Triangles alternately pointing up (`[i, j]` with `i + j` even) and
+    /// down: a turmite leaves through one of the 3 sides, so it always
+    /// turns. The 6 directions are clockwise from 30 degrees (up and
+    /// right), and only `Left`, `Right` and `UTurn` are possible.
+    Triangular,
+}
+
+impl Lattice {
+    /// Number of directions
+    pub fn directions(self) -> usize {
+        match self {
+            Lattice::Square => 4,
+            Lattice::Hex | Lattice::Triangular => 6,
+        }
+    }
+
+    /// Direction after turning from direction `d`
+    fn turn(self, d: usize, t: Turn) -> usize {
+        let n = self.directions();
+        let clockwise = match t {
+            Turn::NoTurn => 0,
+            Turn::Right => 1,
+            Turn::Right2 => 2,
+            Turn::UTurn => n / 2,
+            Turn::Left2 => n - 2,
+            Turn::Left => n - 1,
+        };
+        (d + clockwise) % n
+    }
+
+    /// Next position in direction `d` (with wrapping)
+    fn mv(self, p: Pos, d: usize, imax: usize, jmax: usize) -> Pos {
+        let [i, j] = p;
+        let (di, dj) = match self {
+            Lattice::Square => return mv(p, Dir::from(d), imax, jmax),
+            // E, SE, SW, W, NW, NE
+            Lattice::Hex => {
+                let odd = (i % 2) as isize;
+                match d {
+                    0 => (0, 1),
+                    1 => (1, odd),
+                    2 => (1, odd - 1),
+                    3 => (0, -1),
+                    4 => (-1, odd - 1),
+                    _ => (-1, odd),
+                }
+            }
+            // 30, 330, 270, 210, 150 and 90 degrees: up triangles are left
+            // through sides 0 (right), 2 (bottom) and 4 (left), down
+            // triangles through 1 (right), 3 (left) and 5 (top)
+            Lattice::Triangular => match d {
+                0 | 1 => (0, 1),
+                2 => (1, 0),
+                3 | 4 => (0, -1),
+                _ => (-1, 0),
+            },
+        };
+        let wrap =
+            |x: usize, dx: isize, n: usize| (x as isize + dx).rem_euclid(n as isize) as usize;
+        [wrap(i, di, imax), wrap(j, dj, jmax)]
+    }
+}
+
+/// Transition table for how to move the turmite: a turmite in state `s` on
+/// a cell marked `m` replaces the mark, turns and changes its state
+/// according to `rules[s][m]`, then moves forward.
+pub type Rules<'a> = &'a [&'a [(Mark, Turn, State)]];
+
+/// Same as `Rules`, but owning its contents so that it can be built at
+/// runtime
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Table(Vec<Vec<(Mark, Turn, State)>>);
 
 /// Position on the canvas of a turmite
 type Pos = [usize; 2];
@@ -30,52 +124,30 @@ type Pos = [usize; 2];
 #[derive(Clone, Copy)]
 struct Turmite {
     pos: Pos,
-    dir: Dir,
-    rules: usize,
+    /// Index of the direction, see `Lattice`
+    dir: usize,
+    state: State,
 }
 
 /// A collection of turmites, along with their environment
-pub struct Mound<'a> {
+pub struct Mound {
     hgt: usize,
     wth: usize,
-    map: Rules<'a>,
+    lattice: Lattice,
+    map: Table,
     field: Canvas<Mark>,
     turmites: Vec<Turmite>,
     cnt: usize,
 }
 
 impl Dir {
-    /// Update new direction
-    fn turn(self, t: Turn) -> Dir {
+    /// Inverse of `from`
+    fn index(self) -> usize {
         match self {
-            Dir::N => {
-                if t == Turn::Left {
-                    Dir::W
-                } else {
-                    Dir::E
-                }
-            }
-            Dir::S => {
-                if t == Turn::Left {
-                    Dir::E
-                } else {
-                    Dir::W
-                }
-            }
-            Dir::E => {
-                if t == Turn::Left {
-                    Dir::N
-                } else {
-                    Dir::S
-                }
-            }
-            Dir::W => {
-                if t == Turn::Left {
-                    Dir::S
-                } else {
-                    Dir::N
-                }
-            }
+            Dir::N => 0,
+            Dir::E => 1,
+            Dir::S => 2,
+            Dir::W => 3,
         }
     }
 
@@ -106,50 +178,95 @@ impl Colorize for Mark {
     }
 }
 
-impl<'a> Mound<'a> {
-    /// Create mound with no turmites and a blank environment
-    pub fn new(hgt: usize, wth: usize, rules: Rules<'a>) -> Self {
+impl Mound {
+    /// Create mound with no turmites and a blank environment.
+    /// `rules` is either a constant such as `RULES_2` or a parsed `Table`.
+    pub fn new<R: Into<Table>>(hgt: usize, wth: usize, rules: R) -> Self {
+        Self::with(hgt, wth, rules, Lattice::Square)
+    }
+
+    /// Same as `new`, on another lattice. Its dimensions must be even so
+    /// that it can wrap around edges.
+    pub fn with<R: Into<Table>>(hgt: usize, wth: usize, rules: R, lattice: Lattice) -> Self {
+        let map = rules.into();
+        match lattice {
+            Lattice::Square => (),
+            Lattice::Hex => assert_eq!(hgt % 2, 0, "a hexagonal grid must have an even height"),
+            Lattice::Triangular => {
+                assert_eq!(
+                    (hgt % 2, wth % 2),
+                    (0, 0),
+                    "a triangular grid must have even dimensions"
+                );
+                let possible = |t: &Turn| matches!(t, Turn::Left | Turn::Right | Turn::UTurn);
+                assert!(
+                    map.0.iter().flatten().all(|(_, t, _)| possible(t)),
+                    "turmites can only turn left, right or back on a triangular grid"
+                );
+            }
+        }
         Self {
             hgt,
             wth,
-            map: rules,
+            lattice,
+            map,
             field: Canvas::new(hgt, wth, 0),
             turmites: Vec::new(),
             cnt: 0,
         }
     }
 
-    /// Add a turmite
-    pub fn add(&mut self, pos: Pos, dir: Dir, rules: usize) {
-        self.turmites.push(Turmite { pos, dir, rules });
+    /// Mark on a cell
+    pub fn get(&self, pos: Pos) -> Mark {
+        self.field[pos]
+    }
+
+    /// Add a turmite on a square grid
+    pub fn add(&mut self, pos: Pos, dir: Dir, state: State) {
+        self.add_heading(pos, dir.index(), state);
+    }
+
+    /// Add a turmite going in the given direction (see `Lattice`).
+    /// On a triangular grid, the direction is changed if needed to one
+    /// through which the turmite could have entered its cell, so that it
+    /// leaves through a side after its first turn.
+    pub fn add_heading(&mut self, pos: Pos, dir: usize, state: State) {
+        let mut dir = dir % self.lattice.directions();
+        if self.lattice == Lattice::Triangular && (pos[0] + pos[1]) % 2 == dir % 2 {
+            dir = (dir + 1) % 6;
+        }
+        self.turmites.push(Turmite { pos, dir, state });
     }
 
     /// Add a randomly generated turmite with restrictions on the range
-    /// of positions and the possible orientations.
+    /// of positions and the possible orientations. It starts in the given
+    /// state, or a random one.
     pub fn add_rand(
         &mut self,
         [imin, imax]: [usize; 2],
         [jmin, jmax]: [usize; 2],
-        rules_rng: Option<usize>,
+        state: Option<State>,
     ) {
         let mut rng = rand::thread_rng();
-        self.turmites.push(Turmite {
-            pos: [rng.gen_range(imin, imax), rng.gen_range(jmin, jmax)],
-            dir: Dir::from(rng.gen_range(0, 4)),
-            rules: match rules_rng {
-                Some(n) => n,
-                None => rng.gen_range(0, self.map.len()),
-            },
-        });
+        let pos = [rng.gen_range(imin, imax), rng.gen_range(jmin, jmax)];
+        let dir = rng.gen_range(0, self.lattice.directions());
+        let state = match state {
+            Some(n) => n,
+            None => rng.gen_range(0, self.map.0.len()),
+        };
+        self.add_heading(pos, dir, state);
     }
 
     /// Make all turmites by one step
     pub fn next(&mut self) {
         for turmite in &mut self.turmites {
-            let (m, t) = self.map[turmite.rules][self.field[turmite.pos]];
-            turmite.turn(t);
+            let (m, t, s) = self.map.0[turmite.state][self.field[turmite.pos]];
+            turmite.dir = self.lattice.turn(turmite.dir, t);
+            turmite.state = s;
             self.field[turmite.pos] = m;
-            turmite.mv(self.hgt, self.wth);
+            turmite.pos = self
+                .lattice
+                .mv(turmite.pos, turmite.dir, self.hgt, self.wth);
         }
         self.cnt += 1;
     }
@@ -164,24 +281,278 @@ impl<'a> Mound<'a> {
     /// Create image from current state
     pub fn render(&mut self, cfg: &mut crate::Config) {
         let name = cfg.frame();
-        self.field.render(&name);
+        match self.lattice {
+            Lattice::Square => self.field.render(&name),
+            Lattice::Hex => self.hex_pixels().render(&name),
+            Lattice::Triangular => self.triangle_pixels().render(&name),
+        }
 
         eprint!("\rDone frame {} ({}'th movement)", name, self.cnt);
     }
 }
 
-impl Turmite {
-    /// Change direction
-    pub fn turn(&mut self, t: Turn) {
-        self.dir = self.dir.turn(t);
+/// Radius of a hexagonal cell, in pixels
+const HEX_RADIUS: f64 = 4.;
+
+/// Side and height of a triangular cell, in pixels
+const TRIANGLE: [usize; 2] = [8, 7];
+
+impl Mound {
+    /// Draw each cell of a hexagonal grid as a hexagon: a pixel belongs to
+    /// the cell with the nearest center.
+    fn hex_pixels(&self) -> Canvas<Mark> {
+        let r = HEX_RADIUS;
+        let w = 3f64.sqrt() * r;
+        let hgt = (1.5 * r * self.hgt as f64 + 0.5 * r).ceil() as usize;
+        let wth = (w * (self.wth as f64 + 0.5)).ceil() as usize;
+        let mut px = Canvas::new(hgt, wth, 0);
+        for y in 0..hgt {
+            for x in 0..wth {
+                let (py, pxx) = (y as f64 + 0.5, x as f64 + 0.5);
+                let mut best: Option<(f64, Pos)> = None;
+                let ci = ((py - r) / (1.5 * r)).round() as isize;
+                for i in ci - 1..=ci + 1 {
+                    if i < 0 || i >= self.hgt as isize {
+                        continue;
+                    }
+                    let shift = if i % 2 == 1 { 0.5 } else { 0. };
+                    let cj = (pxx / w - 0.5 - shift).round() as isize;
+                    for j in cj - 1..=cj + 1 {
+                        if j < 0 || j >= self.wth as isize {
+                            continue;
+                        }
+                        let cy = r + 1.5 * r * i as f64;
+                        let cx = w * (j as f64 + 0.5 + shift);
+                        let d = (py - cy).powi(2) + (pxx - cx).powi(2);
+                        match best {
+                            Some((bd, _)) if bd <= d => (),
+                            _ => best = Some((d, [i as usize, j as usize])),
+                        }
+                    }
+                }
+                match best {
+                    Some((d, pos)) if d <= r * r => px[[y, x]] = self.field[pos],
+                    _ => (),
+                }
+            }
+        }
+        px
+    }
+
+    /// Draw each cell of a triangular grid as a triangle, each line
+    /// alternating between triangles pointing up and down
+    fn triangle_pixels(&self) -> Canvas<Mark> {
+        let [side, height] = TRIANGLE;
+        let half = side / 2;
+        let mut px = Canvas::new(height * self.hgt, half * (self.wth + 1), 0);
+        for y in 0..height * self.hgt {
+            let i = y / height;
+            // position within the line, from 0 (top) to 1 (bottom)
+            let v = ((y % height) as f64 + 0.5) / height as f64;
+            for x in 0..half * (self.wth + 1) {
+                // the column `j` is split between triangles `j - 1` and `j`
+                // by one of their sides
+                let j = x / half;
+                let u = ((x % half) as f64 + 0.5) / half as f64;
+                let up = (i + j) % 2 == 0;
+                let inside = if up { v >= 1. - u } else { v <= u };
+                let cell = if inside { Some(j) } else { j.checked_sub(1) };
+                match cell {
+                    Some(j) if j < self.wth => px[[y, x]] = self.field[[i, j]],
+                    _ => (),
+                }
+            }
+        }
+        px
+    }
+}
+
+impl From<Rules<'_>> for Table {
+    fn from(rules: Rules) -> Self {
+        Table(rules.iter().map(|r| r.to_vec()).collect())
+    }
+}
+
+impl Table {
+    /// Read either an ant string (see `ant`) or a turmite in Ed Pegg's
+    /// notation (see `pegg`)
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        if s.trim_start().starts_with('{') {
+            Self::pegg(s)
+        } else {
+            Self::ant(s)
+        }
+    }
+
+    /// Langton's ant and its generalizations: the `k`-th letter (`L`, `R`,
+    /// `N` for no turn or `U` for a U-turn) is the turn taken on a cell
+    /// marked `k`, which is then marked `k + 1` (cycling back to 0 after
+    /// the last letter).
+    /// `RL` is the usual ant, `LLRR` and `RRLLLRLLLRRR` draw symmetric
+    /// and triangular patterns.
+    ///
+    /// Hexagonal ants use `L1`, `R1` (same as `L`, `R`) and `L2`, `R2`
+    /// (see `Turn`), e.g. `L2NNL1L2L1`.
+    ///
+    /// The result has a single state.
+    pub fn ant(s: &str) -> Result<Self, ParseError> {
+        let s = s.trim();
+        let mut turns = Vec::new();
+        let mut chars = s.chars().enumerate().peekable();
+        while let Some((k, c)) = chars.next() {
+            let twice = match chars.peek() {
+                Some((_, '1')) => Some(false),
+                Some((_, '2')) => Some(true),
+                _ => None,
+            };
+            if twice.is_some() && matches!(c, 'L' | 'l' | 'R' | 'r') {
+                chars.next();
+            }
+            match (c, twice) {
+                ('L', Some(true)) | ('l', Some(true)) => turns.push(Turn::Left2),
+                ('R', Some(true)) | ('r', Some(true)) => turns.push(Turn::Right2),
+                ('L', _) | ('l', _) => turns.push(Turn::Left),
+                ('R', _) | ('r', _) => turns.push(Turn::Right),
+                ('N', _) | ('n', _) => turns.push(Turn::NoTurn),
+                ('U', _) | ('u', _) => turns.push(Turn::UTurn),
+                _ => {
+                    return Err(ParseError::Unexpected {
+                        line: 1,
+                        col: k + 1,
+                        c,
+                    })
+                }
+            }
+        }
+        if turns.is_empty() {
+            return Err(ParseError::Invalid {
+                line: 1,
+                msg: String::from("an ant needs at least one turn"),
+            });
+        }
+        let n = turns.len();
+        Ok(Table(vec![turns
+            .into_iter()
+            .enumerate()
+            .map(|(k, t)| ((k + 1) % n, t, 0))
+            .collect()]))
     }
 
-    /// Step forward by one (with wrapping around edges)
-    pub fn mv(&mut self, imax: usize, jmax: usize) {
-        self.pos = mv(self.pos, self.dir, imax, jmax);
+    /// Turmite in the notation of Ed Pegg Jr, as used on Wolfram MathWorld
+    /// and in Golly: `{{{1,2,0},{0,8,0}}}` is Langton's ant.
+    ///
+    /// There is one `{...}` per internal state, containing one `{mark, turn,
+    /// state}` per mark on the current cell: the new mark, the turn
+    /// (`1`: none, `2`: right, `4`: U-turn, `8`: left) and the new state.
+    /// `{{{1,8,1},{1,8,1}},{{1,2,1},{0,1,0}}}` draws a Fibonacci spiral.
+    pub fn pegg(s: &str) -> Result<Self, ParseError> {
+        let states = pegg_lists(s)?;
+        let invalid = |msg: String| ParseError::Invalid { line: 1, msg };
+        let marks = states[0].len();
+        let mut table = Vec::new();
+        for state in &states {
+            let mut row = Vec::new();
+            for &[m, t, st] in state {
+                if m >= marks || st >= states.len() {
+                    return Err(invalid(format!(
+                        "{{{},{},{}}} refers to a mark or state that does not exist",
+                        m, t, st
+                    )));
+                }
+                let turn = match t {
+                    1 => Turn::NoTurn,
+                    2 => Turn::Right,
+                    4 => Turn::UTurn,
+                    8 => Turn::Left,
+                    _ => return Err(invalid(format!("{} is not a turn", t))),
+                };
+                row.push((m, turn, st));
+            }
+            table.push(row);
+        }
+        Ok(Table(table))
     }
 }
 
+/// Split Ed Pegg's notation into states, each a list of `[mark, turn,
+/// state]`. All states must have the same number of entries.
+fn pegg_lists(s: &str) -> Result<Vec<Vec<[usize; 3]>>, ParseError> {
+    let mut states = Vec::new();
+    let mut chars = s.chars().enumerate().filter(|(_, c)| !c.is_whitespace());
+    let unexpected = |(k, c): (usize, char)| ParseError::Unexpected {
+        line: 1,
+        col: k + 1,
+        c,
+    };
+    let truncated = || ParseError::Invalid {
+        line: 1,
+        msg: String::from("unexpected end of the turmite"),
+    };
+    // the structure is always 3 levels of lists, `depth` is the current one
+    let mut depth = 0;
+    let mut num: Option<usize> = None;
+    let mut triple = Vec::new();
+    // lists at the same level are separated by commas
+    let mut after_list = false;
+    loop {
+        let (k, c) = chars.next().ok_or_else(truncated)?;
+        match (c, depth) {
+            ('{', 0) | ('{', 1) if !after_list => {
+                depth += 1;
+                if depth == 2 {
+                    states.push(Vec::new());
+                }
+            }
+            ('{', 2) if !after_list => {
+                depth += 1;
+                triple.clear();
+            }
+            ('0'..='9', 3) => {
+                let d = c.to_digit(10).unwrap() as usize;
+                num = Some(num.unwrap_or(0) * 10 + d);
+            }
+            (',', 3) | ('}', 3) => {
+                triple.push(num.take().ok_or_else(|| unexpected((k, c)))?);
+                if c == '}' {
+                    match triple[..] {
+                        [m, t, st] => states.last_mut().unwrap().push([m, t, st]),
+                        _ => {
+                            return Err(ParseError::Invalid {
+                                line: 1,
+                                msg: String::from("expected `{mark,turn,state}`"),
+                            })
+                        }
+                    }
+                    depth -= 1;
+                    after_list = true;
+                }
+            }
+            (',', 1) | (',', 2) if after_list => after_list = false,
+            ('}', 1) | ('}', 2) if after_list => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => return Err(unexpected((k, c))),
+        }
+    }
+    if let Some(rest) = chars.next() {
+        return Err(unexpected(rest));
+    }
+    if states.is_empty()
+        || states
+            .iter()
+            .any(|st| st.is_empty() || st.len() != states[0].len())
+    {
+        return Err(ParseError::Invalid {
+            line: 1,
+            msg: String::from("all states must have the same number of marks"),
+        });
+    }
+    Ok(states)
+}
+
 /// Calculate next position depending on direction (with wrapping)
 fn mv(p: Pos, d: Dir, imax: usize, jmax: usize) -> Pos {
     match d {
@@ -216,22 +587,192 @@ fn mv(p: Pos, d: Dir, imax: usize, jmax: usize) -> Pos {
     }
 }
 
+/// Two ants that turn in opposite directions
 pub const RULES_2: Rules = &[
-    &[(1, Turn::Left), (0, Turn::Right)],
-    &[(1, Turn::Right), (0, Turn::Left)],
+    &[(1, Turn::Left, 0), (0, Turn::Right, 0)],
+    &[(1, Turn::Right, 1), (0, Turn::Left, 1)],
 ];
 
+/// Two `LRRL` ants that turn in opposite directions
 pub const RULES_4: Rules = &[
     &[
-        (1, Turn::Left),
-        (2, Turn::Right),
-        (3, Turn::Right),
-        (0, Turn::Left),
+        (1, Turn::Left, 0),
+        (2, Turn::Right, 0),
+        (3, Turn::Right, 0),
+        (0, Turn::Left, 0),
     ],
     &[
-        (1, Turn::Right),
-        (2, Turn::Left),
-        (3, Turn::Left),
-        (0, Turn::Right),
+        (1, Turn::Right, 1),
+        (2, Turn::Left, 1),
+        (3, Turn::Left, 1),
+        (0, Turn::Right, 1),
     ],
 ];
+
+/// 2-state turmite that draws a growing Fibonacci spiral
+pub const FIBONACCI: Rules = &[
+    &[(1, Turn::Left, 1), (1, Turn::Left, 1)],
+    &[(1, Turn::Right, 1), (0, Turn::NoTurn, 0)],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ant_strings() {
+        let lr = |t| vec![(1, t, 0), (0, t, 0)];
+        assert_eq!(
+            Table::ant("LR").unwrap(),
+            Table(vec![vec![(1, Turn::Left, 0), (0, Turn::Right, 0)]])
+        );
+        assert_eq!(Table::from(&RULES_2[..1]), Table::parse(" lr ").unwrap());
+        assert_eq!(Table::ant("NU").unwrap().0[0][1], (0, Turn::UTurn, 0));
+        assert_eq!(
+            Table::ant("L2R1R2").unwrap().0[0]
+                .iter()
+                .map(|r| r.1)
+                .collect::<Vec<_>>(),
+            vec![Turn::Left2, Turn::Right, Turn::Right2]
+        );
+        assert_eq!(Table::ant("LL").unwrap().0[0], lr(Turn::Left));
+        match Table::ant("RLX") {
+            Err(ParseError::Unexpected {
+                line: 1,
+                col: 3,
+                c: 'X',
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+        // a digit only makes sense after `L` or `R`
+        assert!(matches!(
+            Table::ant("N2"),
+            Err(ParseError::Unexpected { col: 2, .. })
+        ));
+        assert!(matches!(Table::ant("  "), Err(ParseError::Invalid { .. })));
+    }
+
+    #[test]
+    fn pegg_notation() {
+        assert_eq!(
+            Table::parse("{{{1,2,0},{0,8,0}}}").unwrap(),
+            Table::ant("RL").unwrap()
+        );
+        assert_eq!(
+            Table::parse("{{{1, 8, 1}, {1, 8, 1}}, {{1, 2, 1}, {0, 1, 0}}}").unwrap(),
+            Table::from(FIBONACCI)
+        );
+        assert_eq!(
+            Table::pegg("{{{1,4,0},{0,1,0}}}").unwrap().0[0],
+            vec![(1, Turn::UTurn, 0), (0, Turn::NoTurn, 0)]
+        );
+        let errors = [
+            ("{{{1,2,0},{0,8}}}", None),
+            ("{{{1,3,0},{0,8,0}}}", None),
+            ("{{{2,2,0},{0,8,0}}}", None),
+            ("{{{1,2,1},{0,8,0}}}", None),
+            ("{{{1,2,0},{0,8,0}},{{1,2,0}}}", None),
+            ("{{{1,2,0},{0,8,0}", None),
+            ("{{{1,2,0},{0,8,0}}}}", Some(20)),
+            ("{{{1,2,0}{0,8,0}}}", Some(10)),
+            ("{{{1,,0}}}", Some(6)),
+        ];
+        for &(s, col) in &errors {
+            match (Table::pegg(s), col) {
+                (Err(ParseError::Unexpected { col: c, .. }), Some(col)) => {
+                    assert_eq!(c, col, "{}", s)
+                }
+                (Err(ParseError::Invalid { .. }), None) => (),
+                (other, _) => panic!("{}: {:?}", s, other),
+            }
+        }
+    }
+
+    #[test]
+    fn turns() {
+        let sq = Lattice::Square;
+        assert_eq!(sq.turn(Dir::N.index(), Turn::Right), Dir::E.index());
+        assert_eq!(sq.turn(Dir::N.index(), Turn::Left), Dir::W.index());
+        assert_eq!(sq.turn(Dir::E.index(), Turn::UTurn), Dir::W.index());
+        assert_eq!(sq.turn(Dir::S.index(), Turn::NoTurn), Dir::S.index());
+        let hex = Lattice::Hex;
+        assert_eq!(hex.turn(0, Turn::Left2), 4);
+        assert_eq!(hex.turn(5, Turn::Right2), 1);
+        assert_eq!(hex.turn(1, Turn::UTurn), 4);
+    }
+
+    #[test]
+    fn langton() {
+        let mut mound = Mound::new(10, 10, Table::ant("RL").unwrap());
+        mound.add([5, 5], Dir::N, 0);
+        let mut path = Vec::new();
+        for _ in 0..5 {
+            path.push(mound.turmites[0].pos);
+            mound.next();
+        }
+        // a square turning right, then left on the first marked cell
+        assert_eq!(path, vec![[5, 5], [5, 6], [6, 6], [6, 5], [5, 5]]);
+        assert_eq!(mound.turmites[0].pos, [5, 4]);
+        assert_eq!(mound.get([5, 5]), 0);
+        assert_eq!(mound.get([6, 6]), 1);
+    }
+
+    #[test]
+    fn straight_and_back() {
+        let mut mound = Mound::new(6, 6, Table::ant("N").unwrap());
+        mound.add([2, 5], Dir::E, 0);
+        mound.multi(3);
+        // wraps around the edge
+        assert_eq!(mound.turmites[0].pos, [2, 2]);
+        let mut mound = Mound::new(6, 6, Table::ant("U").unwrap());
+        mound.add([2, 2], Dir::N, 0);
+        mound.multi(3);
+        assert_eq!(mound.turmites[0].pos, [3, 2]);
+    }
+
+    #[test]
+    fn states() {
+        let mut mound = Mound::new(20, 20, FIBONACCI);
+        mound.add([10, 10], Dir::N, 0);
+        mound.next();
+        assert_eq!(mound.turmites[0].state, 1);
+        assert_eq!(mound.get([10, 10]), 1);
+        // state 1 on blank cells: turn right around the first mark
+        mound.multi(3);
+        assert_eq!(mound.turmites[0].state, 1);
+        assert_eq!(mound.turmites[0].pos, [10, 10]);
+        // state 1 on a marked cell: erase it, go straight, back to state 0
+        mound.next();
+        assert_eq!(mound.turmites[0].state, 0);
+        assert_eq!(mound.turmites[0].pos, [11, 10]);
+        assert_eq!(mound.get([10, 10]), 0);
+    }
+
+    #[test]
+    fn triangular_turmites_leave_through_sides() {
+        let mut mound = Mound::with(8, 8, Table::parse("RLUR").unwrap(), Lattice::Triangular);
+        for i in 0..8 {
+            for dir in 0..6 {
+                mound.add_heading([i, (3 * i + dir) % 8], dir, 0);
+            }
+        }
+        for _ in 0..200 {
+            let before = mound.turmites.clone();
+            mound.next();
+            for (b, a) in before.iter().zip(&mound.turmites) {
+                let [i, j] = b.pos;
+                let up = (i + j) % 2 == 0;
+                // sides of up triangles: right, bottom, left;
+                // sides of down triangles: right, left, top
+                let expected = match (up, a.dir) {
+                    (true, 0) | (false, 1) => [i, (j + 1) % 8],
+                    (true, 2) => [(i + 1) % 8, j],
+                    (true, 4) | (false, 3) => [i, (j + 7) % 8],
+                    (false, 5) => [(i + 7) % 8, j],
+                    _ => panic!("left {:?} through a corner (direction {})", b.pos, a.dir),
+                };
+                assert_eq!(a.pos, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::canvas::*;
+use crate::sandpile::{shade, Avalanche};
+
+/// Energy at which a site topples
+pub const CRITICAL: f64 = 1.;
+
+/// Energy of a site, colored like a sandpile of threshold 4
+#[derive(Clone, Copy)]
+struct Energy(f64);
+
+impl Colorize for Energy {
+    fn color(&self) -> Color {
+        let level = if self.0 <= 0. {
+            0
+        } else {
+            1 + ((self.0 / CRITICAL * 3.) as usize).min(3)
+        };
+        shade(level, 4)
+    }
+}
+
+/// Zhang's sandpile: heights are continuous. A site that reaches the
+/// critical energy gives all of it to its 4 neighbors in equal parts,
+/// the energy that falls off the border is lost.
+pub struct Zhang {
+    hgt: usize,
+    wth: usize,
+    energy: Vec<f64>,
+    /// Largest amount of energy added by `drop_grain`
+    pub drive: f64,
+    rng: ChaCha8Rng,
+    cnt: usize,
+}
+
+impl Zhang {
+    /// Initialize an empty pile, `seed` initializes the amounts added by
+    /// `drop_grain`
+    pub fn new(hgt: usize, wth: usize, seed: u64) -> Self {
+        Self {
+            hgt,
+            wth,
+            energy: vec![0.; hgt * wth],
+            drive: 0.5,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            cnt: 0,
+        }
+    }
+
+    /// `[hgt, wth]`
+    pub fn dims(&self) -> [usize; 2] {
+        [self.hgt, self.wth]
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        self.energy[i * self.wth + j]
+    }
+
+    /// Sites that are within the field and next to `[i, j]`
+    fn neighbors(&self, i: usize, j: usize) -> impl Iterator<Item = [usize; 2]> {
+        let (hgt, wth) = (self.hgt as isize, self.wth as isize);
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .map(move |(di, dj)| [i as isize + di, j as isize + dj])
+            .filter(move |&[ni, nj]| ni >= 0 && nj >= 0 && ni < hgt && nj < wth)
+            .map(|[ni, nj]| [ni as usize, nj as usize])
+    }
+
+    /// Add a random amount of energy (up to `drive`) to a site and relax
+    /// the pile wave by wave, keeping track of the avalanche that this
+    /// causes
+    pub fn drop_grain(&mut self, i: usize, j: usize) -> Avalanche {
+        let mut aval = Avalanche {
+            pos: [i, j],
+            size: 0,
+            area: 0,
+            waves: 0,
+            radius: 0.,
+        };
+        let mut toppled = vec![false; self.hgt * self.wth];
+        let mut queue = Vec::new();
+        let amount = self.rng.gen_range(0.0, self.drive);
+        self.energy[i * self.wth + j] += amount;
+        while self.get(i, j) >= CRITICAL {
+            aval.waves += 1;
+            queue.push([i, j]);
+            while let Some([ci, cj]) = queue.pop() {
+                let e = self.get(ci, cj);
+                if e < CRITICAL {
+                    continue;
+                }
+                self.energy[ci * self.wth + cj] = 0.;
+                aval.size += 1;
+                if !toppled[ci * self.wth + cj] {
+                    toppled[ci * self.wth + cj] = true;
+                    aval.area += 1;
+                    let (di, dj) = (ci as f64 - i as f64, cj as f64 - j as f64);
+                    aval.radius = aval.radius.max((di * di + dj * dj).sqrt());
+                }
+                let targets: Vec<_> = self.neighbors(ci, cj).collect();
+                for [ni, nj] in targets {
+                    self.energy[ni * self.wth + nj] += e / 4.;
+                    if self.get(ni, nj) >= CRITICAL && [ni, nj] != [i, j] {
+                        queue.push([ni, nj]);
+                    }
+                }
+            }
+        }
+        self.cnt += aval.size;
+        aval
+    }
+
+    /// Print output to file
+    pub fn render(&mut self, cfg: &mut crate::Config) {
+        let mut field = Canvas::new(self.hgt, self.wth, Energy(0.));
+        for i in 0..self.hgt {
+            for j in 0..self.wth {
+                field[[i, j]] = Energy(self.get(i, j));
+            }
+        }
+        let name = cfg.frame();
+        field.render(&name);
+
+        eprint!("\rDone rendering frame {} : workload {}", name, self.cnt);
+        self.cnt = 0;
+    }
+}